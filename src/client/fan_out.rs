@@ -0,0 +1,220 @@
+//! Sending many requests with a bounded number in flight.
+
+use http::{Request, Response};
+use std::collections::VecDeque;
+
+use crate::client::{select, PendingRequest, RequestExt, SendError};
+
+/// Sends a sequence of tagged requests asynchronously, keeping at most a fixed number in flight,
+/// and yields their results in the order they complete.
+///
+/// Each item is paired with the tag of its request, so that results can be matched back to the
+/// requests without comparing handles. A request that fails, including one that could not be
+/// started, yields its own error without affecting the others. If the host fails without
+/// identifying which request completed, its error is yielded for every request in flight, all of
+/// which are abandoned.
+///
+/// ```text
+/// let requests = ids.iter().map(|id| {
+///     let req = Request::get(format!("https://users/{}", id)).body(vec![]).unwrap();
+///     (id, req)
+/// });
+/// for (id, result) in FanOut::new(requests, 8) {
+///     match result {
+///         Ok(resp) => users.insert(id, resp.into_body()),
+///         Err(e) => errors.push((id, e)),
+///     }
+/// }
+/// ```
+pub struct FanOut<T, I> {
+    requests: I,
+    in_flight: Vec<(T, PendingRequest)>,
+    /// Abandoned requests whose errors have yet to be yielded.
+    failed: VecDeque<(T, SendError)>,
+    max_in_flight: usize,
+}
+
+impl<T, I> FanOut<T, I>
+where
+    I: Iterator<Item = (T, Request<Vec<u8>>)>,
+{
+    /// Prepare to send `requests`, with at most `max_in_flight` of them in flight at once.
+    ///
+    /// No requests are sent until the first result is asked for. A `max_in_flight` of zero is
+    /// treated as one.
+    pub fn new<R>(requests: R, max_in_flight: usize) -> FanOut<T, I>
+    where
+        R: IntoIterator<IntoIter = I, Item = (T, Request<Vec<u8>>)>,
+    {
+        FanOut {
+            requests: requests.into_iter(),
+            in_flight: vec![],
+            failed: VecDeque::new(),
+            max_in_flight: max_in_flight.max(1),
+        }
+    }
+}
+
+impl<T, I> Iterator for FanOut<T, I>
+where
+    I: Iterator<Item = (T, Request<Vec<u8>>)>,
+{
+    type Item = (T, Result<Response<Vec<u8>>, SendError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((tag, e)) = self.failed.pop_front() {
+            return Some((tag, Err(e)));
+        }
+        while self.in_flight.len() < self.max_in_flight {
+            match self.requests.next() {
+                Some((tag, req)) => match req.send_async() {
+                    Ok(pr) => self.in_flight.push((tag, pr)),
+                    Err(e) => return Some((tag, Err(e))),
+                },
+                None => break,
+            }
+        }
+        if self.in_flight.is_empty() {
+            return None;
+        }
+        let selected = {
+            let prs = self.in_flight.iter().map(|(_, pr)| pr).collect::<Vec<_>>();
+            select(&prs)
+        };
+        match selected {
+            Ok((pos, resp)) => Some((self.in_flight.remove(pos).0, Ok(resp))),
+            Err((Some(pos), e)) => Some((self.in_flight.remove(pos).0, Err(e))),
+            Err((None, e)) => {
+                // the host could not say which request failed, so fail all of them rather than
+                // guessing, as the executor does
+                let failed = self
+                    .in_flight
+                    .drain(..)
+                    .map(|(tag, _)| (tag, e.duplicate()));
+                self.failed.extend(failed);
+                self.failed.pop_front().map(|(tag, e)| (tag, Err(e)))
+            }
+        }
+    }
+}
+
+/// Send all of `requests` with at most `max_in_flight` in flight at once, and collect their
+/// results in the order they complete.
+///
+/// This is `FanOut::new(requests, max_in_flight).collect()`.
+#[allow(clippy::type_complexity)]
+pub fn join_all<T, R>(
+    requests: R,
+    max_in_flight: usize,
+) -> Vec<(T, Result<Response<Vec<u8>>, SendError>)>
+where
+    R: IntoIterator<Item = (T, Request<Vec<u8>>)>,
+{
+    FanOut::new(requests, max_in_flight).collect()
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::{self, guest};
+    use crate::test_util::{get, tagged_upstream};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn requests() -> Vec<(&'static str, Request<Vec<u8>>)> {
+        ["a", "b", "c", "d"]
+            .iter()
+            .map(|&tag| (tag, get(&format!("http://up/{}", tag))))
+            .collect()
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn tags(results: Vec<(&str, Result<Response<Vec<u8>>, SendError>)>) -> Vec<&str> {
+        results
+            .into_iter()
+            .map(|(tag, result)| {
+                assert_eq!(result.unwrap().body(), tag.as_bytes());
+                tag
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fan_out_yields_results_as_they_complete() {
+        tagged_upstream();
+        let order = guest(|| tags(FanOut::new(requests(), 4).collect()));
+        assert_eq!(order, vec!["d", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn fan_out_only_sends_up_to_the_limit() {
+        tagged_upstream();
+        let order = guest(|| tags(FanOut::new(requests(), 2).collect()));
+        // "c" is sent when "b" completes at 10ms, and "d" when "c" does at 30ms, before "a" at 35ms
+        assert_eq!(order, vec!["b", "c", "d", "a"]);
+        assert_eq!(mock::open_handles(), 0);
+    }
+
+    #[test]
+    fn fan_out_never_exceeds_limit_in_flight() {
+        let in_flight = Rc::new(Cell::new(0));
+        let max_in_flight = Rc::new(Cell::new(0));
+        {
+            let (in_flight, max_in_flight) = (in_flight.clone(), max_in_flight.clone());
+            mock::set_upstream(move |_: &Request<Vec<u8>>| {
+                // the host holds every other request that has been sent but not selected
+                in_flight.set(mock::open_handles() + 1);
+                max_in_flight.set(max_in_flight.get().max(in_flight.get()));
+                Some(Response::new(vec![]))
+            });
+        }
+        guest(|| {
+            let requests = (0..10).map(|i| (i, get("http://up/")));
+            assert_eq!(FanOut::new(requests, 3).count(), 10);
+        });
+        assert_eq!(max_in_flight.get(), 3);
+        assert_eq!(mock::sent_requests().len(), 10);
+    }
+
+    #[test]
+    fn fan_out_yields_failures_with_their_tags() {
+        tagged_upstream();
+        guest(|| {
+            let requests = vec![("ok", get("http://up/b")), ("down", get("http://down/"))];
+            let mut results = FanOut::new(requests, 2).collect::<Vec<_>>();
+            results.sort_by_key(|(tag, _)| *tag);
+            assert!(matches!(results[0], ("down", Err(SendError::Connect(_)))));
+            assert!(matches!(results[1], ("ok", Ok(_))));
+        });
+    }
+
+    #[test]
+    fn fan_out_fails_every_request_when_the_host_cannot_say_which_failed() {
+        mock::set_upstream(|req: &Request<Vec<u8>>| {
+            if req.uri() == "http://breaks/" {
+                // lose track of the requests already in flight
+                mock::forget_pending_requests();
+            }
+            Some(Response::new(vec![]))
+        });
+        guest(|| {
+            let requests = vec![
+                ("a", get("http://up/")),
+                ("b", get("http://breaks/")),
+                ("c", get("http://up/")),
+            ];
+            let results = FanOut::new(requests, 2).collect::<Vec<_>>();
+            assert!(matches!(results[0], ("a", Err(SendError::Hostcall))));
+            assert!(matches!(results[1], ("b", Err(SendError::Hostcall))));
+            assert!(matches!(results[2], ("c", Ok(_))));
+        });
+        assert_eq!(mock::sent_requests().len(), 3);
+    }
+
+    #[test]
+    fn join_all_returns_results_in_completion_order() {
+        tagged_upstream();
+        let order = guest(|| tags(join_all(requests(), 2)));
+        assert_eq!(order, vec!["b", "c", "d", "a"]);
+    }
+}
@@ -0,0 +1,73 @@
+//! Information about how an upstream response was obtained.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::hostcalls::types::{ResponseHandle, ResponseTimings};
+
+/// How the host obtained a response from the upstream.
+///
+/// The client inserts this into the extensions of every response it returns:
+///
+/// ```text
+/// let resp = req.send()?;
+/// let meta = resp.extensions().get::<ResponseMetadata>().unwrap();
+/// if let Some(total) = meta.timings.total {
+///     debug(&format!("{:?} took {:?}", meta.remote_addr, total));
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResponseMetadata {
+    /// How long each phase of the request took.
+    pub timings: Timings,
+    /// The address and port of the upstream, if the host knows them.
+    pub remote_addr: Option<SocketAddr>,
+    /// The TLS protocol version of the connection, such as `TLSv1.3`, if it used TLS.
+    pub tls_version: Option<String>,
+}
+
+/// How long each phase of a request took, measured from when it was sent.
+///
+/// A phase is `None` if it did not happen, such as the DNS lookup and connection for a request
+/// sent on a reused connection, or if the host does not know how long it took.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Timings {
+    /// Resolving the upstream host name.
+    pub dns: Option<Duration>,
+    /// Establishing the TCP connection.
+    pub connect: Option<Duration>,
+    /// The TLS handshake.
+    pub tls: Option<Duration>,
+    /// Waiting for the first byte of the response.
+    pub first_byte: Option<Duration>,
+    /// The whole request, until the response was received.
+    pub total: Option<Duration>,
+}
+
+impl From<ResponseTimings> for Timings {
+    fn from(timings: ResponseTimings) -> Timings {
+        Timings {
+            dns: timings.dns,
+            connect: timings.connect,
+            tls: timings.tls,
+            first_byte: timings.first_byte,
+            total: timings.total,
+        }
+    }
+}
+
+impl ResponseMetadata {
+    /// Look up the metadata of a response from the host.
+    pub(crate) fn from_handle(resp: &ResponseHandle) -> ResponseMetadata {
+        let protocol = resp.get_tls_protocol();
+        ResponseMetadata {
+            timings: Timings::from(resp.get_timings()),
+            remote_addr: resp.get_remote_addr(),
+            tls_version: if protocol.is_empty() {
+                None
+            } else {
+                Some(protocol)
+            },
+        }
+    }
+}
@@ -0,0 +1,676 @@
+use failure::Fail;
+use http::header::HeaderValue;
+use http::{self, Request, Response};
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+mod fan_out;
+mod metadata;
+mod redirect;
+mod retry;
+
+pub use self::fan_out::{join_all, FanOut};
+pub use self::metadata::{ResponseMetadata, Timings};
+pub use self::redirect::RedirectChain;
+pub use self::retry::{Attempts, RetryError, RetryPolicy, RetryingRequest};
+
+use crate::executor::reactor;
+use crate::hostcalls;
+use crate::hostcalls::types::{
+    self as hostcall_types, ErrorCode, HostcallStatus, PendingRequestHandle, RequestHandle,
+    ResponseHandle,
+};
+use crate::trailers::Trailers;
+
+#[derive(Debug, Fail)]
+pub enum SendError {
+    /// An error that arises within the host, for which the host gave
+    /// no more specific reason.
+    #[fail(display = "Hostcall send error")]
+    Hostcall,
+    /// The request method or URL was rejected by the host.
+    #[fail(display = "Invalid URL send error: {}", _0)]
+    InvalidUrl(String),
+    /// The upstream host name could not be resolved.
+    #[fail(display = "DNS send error: {}", _0)]
+    Dns(String),
+    /// The connection to the upstream could not be established.
+    #[fail(display = "Connect send error: {}", _0)]
+    Connect(String),
+    /// The request did not complete in time.
+    #[fail(display = "Timeout send error: {}", _0)]
+    Timeout(String),
+    /// The TLS handshake with the upstream failed.
+    #[fail(display = "TLS send error: {}", _0)]
+    Tls(String),
+    /// The upstream sent a malformed or unexpected message.
+    #[fail(display = "Protocol send error: {}", _0)]
+    Protocol(String),
+    /// The upstream redirected more times than the request allowed.
+    #[fail(display = "Too many redirects send error: more than {}", _0)]
+    TooManyRedirects(u32),
+    /// Can arise during creation of the response within the guest.
+    #[fail(display = "Http send error: {}", _0)]
+    Http(http::Error),
+}
+
+impl SendError {
+    /// Build an error from the most recent failure reported by the
+    /// host.
+    ///
+    /// This must be called straight after the hostcall that failed, as
+    /// the host only keeps its last error until the next hostcall.
+    pub(crate) fn from_host() -> SendError {
+        match hostcalls::last_error() {
+            Some((ErrorCode::InvalidUrl, msg)) => SendError::InvalidUrl(msg),
+            Some((ErrorCode::Dns, msg)) => SendError::Dns(msg),
+            Some((ErrorCode::Connect, msg)) => SendError::Connect(msg),
+            Some((ErrorCode::Timeout, msg)) => SendError::Timeout(msg),
+            Some((ErrorCode::Tls, msg)) => SendError::Tls(msg),
+            Some((ErrorCode::Protocol, msg)) => SendError::Protocol(msg),
+            Some((ErrorCode::None, _)) | Some((ErrorCode::Other, _)) | None => SendError::Hostcall,
+        }
+    }
+
+    /// Copy an error reported by the host, so that it can be reported for each of several
+    /// requests. The host never reports an `Http` error, which cannot be copied and becomes
+    /// `Hostcall`.
+    pub(crate) fn duplicate(&self) -> SendError {
+        match *self {
+            SendError::Hostcall | SendError::Http(_) => SendError::Hostcall,
+            SendError::InvalidUrl(ref msg) => SendError::InvalidUrl(msg.clone()),
+            SendError::Dns(ref msg) => SendError::Dns(msg.clone()),
+            SendError::Connect(ref msg) => SendError::Connect(msg.clone()),
+            SendError::Timeout(ref msg) => SendError::Timeout(msg.clone()),
+            SendError::Tls(ref msg) => SendError::Tls(msg.clone()),
+            SendError::Protocol(ref msg) => SendError::Protocol(msg.clone()),
+            SendError::TooManyRedirects(n) => SendError::TooManyRedirects(n),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PendingRequest(PendingRequestHandle);
+
+pub enum PollResult {
+    NotReady(PendingRequest),
+    Response(Response<Vec<u8>>),
+}
+
+/// Limits on how long an outbound request may take.
+///
+/// Insert this into the extensions of a `Request` before sending it. A request that exceeds
+/// either limit fails with `SendError::Timeout`:
+///
+/// ```text
+/// let mut req = Request::get("https://example.com/").body(vec![])?;
+/// req.extensions_mut()
+///     .insert(Timeouts::new().connect(Duration::from_millis(200)).total(Duration::from_secs(2)));
+/// let resp = req.send()?;
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timeouts {
+    /// The longest the request may take to connect to the upstream.
+    pub connect: Option<Duration>,
+    /// The longest the request may take from being sent until the whole response has arrived.
+    pub total: Option<Duration>,
+}
+
+impl Timeouts {
+    /// No limits; requests may take as long as the host allows.
+    pub fn new() -> Timeouts {
+        Timeouts::default()
+    }
+
+    /// Set the connect timeout.
+    pub fn connect(mut self, timeout: Duration) -> Timeouts {
+        self.connect = Some(timeout);
+        self
+    }
+
+    /// Set the total deadline for the request.
+    pub fn total(mut self, timeout: Duration) -> Timeouts {
+        self.total = Some(timeout);
+        self
+    }
+}
+
+pub trait RequestExt {
+    type R;
+    type Pending;
+
+    /// Synchronously send a request, and return a response.
+    ///
+    /// This will consume the request and block until returning either a response, or a
+    /// `SendError` describing whether the request was rejected, failed in the host, or produced a
+    /// response that could not be converted.
+    ///
+    /// The request is sent with the HTTP version in its `version` field, using HTTP/2 with prior
+    /// knowledge if that is `Version::HTTP_2`, and the response reports the version the upstream
+    /// responded with.
+    ///
+    /// The response carries a `ResponseMetadata` extension with the timings, remote address and
+    /// TLS version of the exchange.
+    fn send(self) -> Result<Self::R, SendError>;
+
+    /// Asynchronously send a request.
+    ///
+    /// This will consume the request and immediately return either a pending request, or a
+    /// `SendError` if the request could not be started.
+    fn send_async(self) -> Result<Self::Pending, SendError>;
+
+    /// Synchronously send a request, and return a response whose body has not been read yet.
+    ///
+    /// The body of the returned response is its `ResponseHandle`, which implements `Read`, so
+    /// that a large body can be passed on in chunks without holding all of it in guest memory.
+    fn send_streaming(self) -> Result<Response<ResponseHandle>, SendError>;
+
+    /// Synchronously send a request, following up to `max_hops` redirects.
+    ///
+    /// Responses with status 301, 302, 303, 307 or 308 and a `Location` header are followed.
+    /// A 303 answering anything but a `HEAD`, or a 301 or 302 answering a `POST`, is followed
+    /// with a `GET` without a body; the others repeat the original method and body. A relative
+    /// `Location` is resolved against the URI that was redirected, and the `Authorization` header
+    /// is dropped once a redirect leaves the original scheme, host and port.
+    ///
+    /// The final response carries a `RedirectChain` extension listing every URI requested. If
+    /// the upstream redirects more than `max_hops` times, returns
+    /// `SendError::TooManyRedirects`.
+    fn send_following_redirects(self, max_hops: u32) -> Result<Self::R, SendError>;
+}
+
+impl RequestExt for Request<Vec<u8>> {
+    type R = Response<Vec<u8>>;
+    type Pending = PendingRequest;
+
+    fn send(self) -> Result<Response<Vec<u8>>, SendError> {
+        let req = prepare_req(self)?;
+
+        let resp_handle = req.send().ok_or_else(SendError::from_host)?;
+
+        build_response(resp_handle)
+    }
+
+    fn send_async(self) -> Result<Self::Pending, SendError> {
+        let req = prepare_req(self)?;
+
+        req.send_async()
+            .map(PendingRequest)
+            .ok_or_else(SendError::from_host)
+    }
+
+    fn send_streaming(self) -> Result<Response<ResponseHandle>, SendError> {
+        let req = prepare_req(self)?;
+
+        let resp_handle = req.send().ok_or_else(SendError::from_host)?;
+
+        response_builder(&resp_handle)
+            .body(resp_handle)
+            .map_err(SendError::Http)
+    }
+
+    fn send_following_redirects(self, max_hops: u32) -> Result<Self::R, SendError> {
+        redirect::send(self, max_hops)
+    }
+}
+
+impl PendingRequest {
+    /// Block until the request has completed.
+    ///
+    /// Consumes the pending request handle, and returns a response. If the request fails, this
+    /// returns a `SendError` describing why.
+    pub fn wait(mut self) -> Result<Response<Vec<u8>>, SendError> {
+        let resp_handle = match self.take() {
+            Ok(pr) => pr.wait().ok_or_else(SendError::from_host)?,
+            Err(result) => result?,
+        };
+
+        build_response(resp_handle)
+    }
+
+    /// Block until the request has completed, or until `timeout` has passed.
+    ///
+    /// If the timeout passes first, returns `PollResult::NotReady(pending_req)`, so that the
+    /// pending request can be used again. Otherwise this behaves like `wait()`, with the response
+    /// returned in `PollResult::Response(resp)`.
+    pub fn wait_timeout(mut self, timeout: Duration) -> Result<PollResult, SendError> {
+        match self.take() {
+            Ok(pr) => PollResult::from_host(pr.wait_timeout(timeout)),
+            Err(result) => result.and_then(build_response).map(PollResult::Response),
+        }
+    }
+
+    /// Poll the status of the pending request without blocking.
+    ///
+    /// If the request has not completed, returns `PollResult::NotReady(pending_req)`, so that the
+    /// pending request can be used again..
+    ///
+    /// If the request has completed, consumes the pending request handle, and returns a response in
+    /// `PollResult::Response(resp)`.
+    ///
+    /// If the request fails, returns a `SendError` describing why.
+    pub fn poll(mut self) -> Result<PollResult, SendError> {
+        match self.take() {
+            Ok(pr) => PollResult::from_host(pr.poll()),
+            Err(result) => result.and_then(build_response).map(PollResult::Response),
+        }
+    }
+
+    /// Abort the request, so that it stops using a connection to the upstream.
+    ///
+    /// Consumes the pending request. Returns `false` if the request had already completed, in
+    /// which case its response or error is discarded.
+    pub fn cancel(mut self) -> bool {
+        match self.take() {
+            Ok(pr) => pr.cancel() == HostcallStatus::Ok,
+            Err(_) => false,
+        }
+    }
+
+    /// Take the handle out of this pending request, and stop the executor waiting on it.
+    ///
+    /// If the request already completed while the executor was waiting on it, returns its result
+    /// instead; the handle is then closed here, since the host has finished with it.
+    fn take(&mut self) -> Result<PendingRequestHandle, Result<ResponseHandle, SendError>> {
+        let pr = mem::replace(&mut self.0, PendingRequestHandle::ERROR);
+        match reactor::forget(i32::from(&pr)) {
+            Some(result) => Err(result),
+            None => Ok(pr),
+        }
+    }
+}
+
+impl PollResult {
+    fn from_host(result: hostcall_types::PollResult) -> Result<PollResult, SendError> {
+        match result {
+            hostcall_types::PollResult::Response(resp_handle) => {
+                build_response(resp_handle).map(PollResult::Response)
+            }
+            hostcall_types::PollResult::NotReady(pr_handle) => {
+                Ok(PollResult::NotReady(PendingRequest(pr_handle)))
+            }
+            hostcall_types::PollResult::Error => Err(SendError::from_host()),
+        }
+    }
+}
+
+/// Dropping a pending request that has been awaited stops the executor waiting on it, and
+/// discards its response if it has already completed.
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        let _ = self.take();
+    }
+}
+
+/// Awaiting a pending request waits for it without blocking other futures run by the same
+/// executor, with the same result as `wait()`.
+///
+/// The request is only driven to completion by `executor::block_on`, or the executor that runs a
+/// `guest_app_async!` entrypoint.
+impl Future for PendingRequest {
+    type Output = Result<Response<Vec<u8>>, SendError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let id = i32::from(&self.0);
+        if let Some(result) = reactor::take_completed(id) {
+            return Poll::Ready(result.and_then(build_response));
+        }
+        let pr = mem::replace(&mut self.0, PendingRequestHandle::ERROR);
+        let result = match pr.poll() {
+            hostcall_types::PollResult::NotReady(pr) => {
+                self.0 = pr;
+                reactor::register(id, cx.waker().clone());
+                return Poll::Pending;
+            }
+            hostcall_types::PollResult::Response(resp_handle) => build_response(resp_handle),
+            hostcall_types::PollResult::Error => Err(SendError::from_host()),
+        };
+        reactor::deregister(id);
+        Poll::Ready(result)
+    }
+}
+
+/// Select from a list of pending requests, blocking until one succeeds, and cancel the rest.
+///
+/// This is for hedged requests: sending the same request to several upstreams, or again after a
+/// delay, and using whichever response arrives first. Returns the position in `prs` of the
+/// request that succeeded, paired with its response. Requests that fail are skipped, and if all
+/// of them fail, returns the error of the last one to do so.
+///
+/// ```text
+/// let prs = vec![
+///     Request::get("https://a.example/").body(vec![])?.send_async()?,
+///     Request::get("https://b.example/").body(vec![])?.send_async()?,
+/// ];
+/// let (_, resp) = select_hedged(prs)?;
+/// ```
+///
+/// Returns `SendError::Hostcall` if `prs` is empty. If the host fails without identifying one of
+/// the requests, the rest are cancelled and its error is returned.
+pub fn select_hedged(prs: Vec<PendingRequest>) -> Result<(usize, Response<Vec<u8>>), SendError> {
+    let mut remaining = prs.into_iter().enumerate().collect::<Vec<_>>();
+    let mut last_error = SendError::Hostcall;
+    while !remaining.is_empty() {
+        let result = {
+            let prs = remaining.iter().map(|(_, pr)| pr).collect::<Vec<_>>();
+            select(&prs)
+        };
+        match result {
+            Ok((pos, resp)) => {
+                let (index, _) = remaining.remove(pos);
+                for (_, pr) in remaining {
+                    pr.cancel();
+                }
+                return Ok((index, resp));
+            }
+            Err((Some(pos), e)) => {
+                remaining.remove(pos);
+                last_error = e;
+            }
+            Err((None, e)) => {
+                last_error = e;
+                break;
+            }
+        }
+    }
+    for (_, pr) in remaining {
+        pr.cancel();
+    }
+    Err(last_error)
+}
+
+/// Select from a list of pending requests, blocking until one completes.
+///
+/// If a request succeeds, returns `Ok((index, resp))` with the position in `prs` of the request
+/// that succeeded, and its response.
+///
+/// If a request fails, returns `Err((Some(index), err))` with the position in `prs` of the
+/// request that failed, and a `SendError` describing why. A request whose response could not be
+/// converted fails with `SendError::Http`. If the host fails without identifying one of the
+/// requests, for example because `prs` is empty or one of them has already completed, returns
+/// `Err((None, err))`.
+///
+/// **Note**: the request at `index` is no longer valid as an argument to `wait`, `poll`, or
+/// `select`, and should be dropped. All other pending requests passed to this function remain
+/// valid for subsequent calls.
+#[allow(clippy::type_complexity)]
+pub fn select(
+    prs: &[&PendingRequest],
+) -> Result<(usize, Response<Vec<u8>>), (Option<usize>, SendError)> {
+    if prs.is_empty() {
+        return Err((None, SendError::Hostcall));
+    }
+    let pr_handles = prs
+        .iter()
+        .map(|pr| &pr.0)
+        .collect::<Vec<&PendingRequestHandle>>();
+    match hostcalls::select(&pr_handles) {
+        Ok((index, resp)) => build_response(resp)
+            .map(|resp| (index, resp))
+            .map_err(|e| (Some(index), e)),
+        Err(index) => Err((index, SendError::from_host())),
+    }
+}
+
+/// Select from a list of pending requests, blocking until one completes or until `timeout` has
+/// passed.
+///
+/// Returns `Ok(None)` if the timeout passes before any request completes, in which case all of
+/// the pending requests remain valid. Otherwise this behaves like `select()`.
+#[allow(clippy::type_complexity)]
+pub fn select_timeout(
+    prs: &[&PendingRequest],
+    timeout: Duration,
+) -> Result<Option<(usize, Response<Vec<u8>>)>, (Option<usize>, SendError)> {
+    if prs.is_empty() {
+        return Err((None, SendError::Hostcall));
+    }
+    let pr_handles = prs
+        .iter()
+        .map(|pr| &pr.0)
+        .collect::<Vec<&PendingRequestHandle>>();
+    match hostcalls::select_timeout(&pr_handles, timeout) {
+        Ok(Some((index, resp))) => build_response(resp)
+            .map(|resp| Some((index, resp)))
+            .map_err(|e| (Some(index), e)),
+        Ok(None) => Ok(None),
+        Err(index) => Err((index, SendError::from_host())),
+    }
+}
+
+/// Copy the parts of a request that are sent to the host, so that it can be sent again.
+fn copy_request(req: &Request<Vec<u8>>) -> Request<Vec<u8>> {
+    let mut copy = Request::new(req.body().clone());
+    *copy.method_mut() = req.method().clone();
+    *copy.uri_mut() = req.uri().clone();
+    *copy.version_mut() = req.version();
+    *copy.headers_mut() = req.headers().clone();
+    if let Some(timeouts) = req.extensions().get::<Timeouts>() {
+        copy.extensions_mut().insert(*timeouts);
+    }
+    if let Some(trailers) = req.extensions().get::<Trailers>() {
+        copy.extensions_mut().insert(trailers.clone());
+    }
+    copy
+}
+
+fn prepare_req(req: Request<Vec<u8>>) -> Result<RequestHandle, SendError> {
+    let (parts, body) = req.into_parts();
+
+    let mut req = RequestHandle::create(parts.method.as_str(), &parts.uri.to_string())
+        .ok_or_else(SendError::from_host)?;
+
+    for key in parts.headers.keys() {
+        let vs = parts
+            .headers
+            .get_all(key)
+            .iter()
+            .map(HeaderValue::as_bytes)
+            .collect::<Vec<&[u8]>>();
+        if req.set_header_bytes(key.as_str(), &vs) == HostcallStatus::Invalid {
+            return Err(SendError::from_host());
+        }
+    }
+
+    if req.set_body(&body) == HostcallStatus::Invalid {
+        return Err(SendError::from_host());
+    }
+
+    if req.set_version(parts.version) == HostcallStatus::Invalid {
+        return Err(SendError::from_host());
+    }
+
+    if let Some(trailers) = parts.extensions.get::<Trailers>() {
+        if trailers.set_on_request(&mut req) == HostcallStatus::Invalid {
+            return Err(SendError::from_host());
+        }
+    }
+
+    if let Some(timeouts) = parts.extensions.get::<Timeouts>() {
+        if req.set_timeouts(timeouts.connect, timeouts.total) == HostcallStatus::Invalid {
+            return Err(SendError::from_host());
+        }
+    }
+
+    Ok(req)
+}
+
+fn build_response(resp_handle: ResponseHandle) -> Result<Response<Vec<u8>>, SendError> {
+    let mut resp = response_builder(&resp_handle)
+        .body(resp_handle.get_body())
+        .map_err(SendError::Http)?;
+    // trailers follow the body, so they are only known once it has been read
+    if let Some(trailers) = Trailers::from_response(&resp_handle) {
+        resp.extensions_mut().insert(trailers);
+    }
+    Ok(resp)
+}
+
+/// Start building a response with the status, version and headers of a response handle.
+fn response_builder(resp_handle: &ResponseHandle) -> http::response::Builder {
+    let mut resp = Response::builder();
+
+    for key in resp_handle.get_headers() {
+        for v in resp_handle.get_header_bytes(&key) {
+            resp.header(key.as_str(), &v[..]);
+        }
+    }
+
+    resp.status(resp_handle.get_response_code())
+        .version(resp_handle.get_version())
+        .extension(ResponseMetadata::from_handle(resp_handle));
+
+    resp
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::{self, guest, FakeUpstream, Reply};
+    use crate::test_util::{ms, send, timed_upstream};
+
+    #[test]
+    fn select_returns_index_of_first_to_complete() {
+        timed_upstream();
+        guest(|| {
+            let prs = [send("http://slow/"), send("http://fast/")];
+            let (index, resp) = select(&prs.iter().collect::<Vec<_>>()).unwrap();
+            assert_eq!((index, resp.status().as_u16()), (1, 201));
+            let (index, resp) = select(&[&prs[0]]).unwrap();
+            assert_eq!((index, resp.status().as_u16()), (0, 200));
+        });
+    }
+
+    #[test]
+    fn select_returns_index_and_error_of_failed_request() {
+        timed_upstream();
+        guest(|| {
+            let prs = [send("http://slow/"), send("http://down/")];
+            match select(&prs.iter().collect::<Vec<_>>()) {
+                Err((Some(1), SendError::Connect(_))) => (),
+                other => panic!("unexpected result: {:?}", other.map(|(i, _)| i)),
+            }
+        });
+    }
+
+    #[test]
+    fn select_on_no_requests_is_an_error() {
+        guest(|| {
+            assert!(matches!(select(&[]), Err((None, SendError::Hostcall))));
+            assert!(matches!(
+                select_timeout(&[], ms(10)),
+                Err((None, SendError::Hostcall))
+            ));
+        });
+    }
+
+    #[test]
+    fn select_on_consumed_request_is_an_error() {
+        timed_upstream();
+        guest(|| {
+            let pr = send("http://fast/");
+            assert_eq!(select(&[&pr]).unwrap().0, 0);
+            // the host has consumed the request, so it cannot tell which one this is
+            assert!(matches!(select(&[&pr]), Err((None, _))));
+        });
+        assert_eq!(mock::open_handles(), 0);
+    }
+
+    #[test]
+    fn select_timeout_leaves_requests_pending() {
+        timed_upstream();
+        guest(|| {
+            let prs = [send("http://slow/"), send("http://fast/")];
+            let prs = prs.iter().collect::<Vec<_>>();
+            assert!(select_timeout(&prs, ms(5)).unwrap().is_none());
+            let (index, _) = select_timeout(&prs, ms(5)).unwrap().unwrap();
+            assert_eq!(index, 1);
+        });
+    }
+
+    #[test]
+    fn select_hedged_takes_first_success_and_cancels_the_rest() {
+        timed_upstream();
+        let ids = guest(|| {
+            let prs = vec![
+                send("http://down/"),
+                send("http://slow/"),
+                send("http://fast/"),
+                send("http://slower/"),
+            ];
+            let ids = prs.iter().map(|pr| i32::from(&pr.0)).collect::<Vec<_>>();
+            let (index, resp) = select_hedged(prs).unwrap();
+            assert_eq!((index, resp.status().as_u16()), (2, 201));
+            ids
+        });
+        assert_eq!(mock::cancelled_handles(), vec![ids[1], ids[3]]);
+        assert_eq!(mock::open_handles(), 0);
+    }
+
+    #[test]
+    fn select_hedged_returns_last_error_if_all_fail() {
+        timed_upstream();
+        guest(|| {
+            let prs = vec![send("http://timeout/"), send("http://down/")];
+            assert!(matches!(select_hedged(prs), Err(SendError::Timeout(_))));
+        });
+        assert!(mock::cancelled_handles().is_empty());
+    }
+
+    #[test]
+    fn select_hedged_returns_host_error_without_panicking() {
+        timed_upstream();
+        guest(|| {
+            let consumed = send("http://fast/");
+            assert_eq!(select(&[&consumed]).unwrap().0, 0);
+            let prs = vec![consumed, send("http://slow/")];
+            assert!(matches!(select_hedged(prs), Err(SendError::Hostcall)));
+        });
+        assert_eq!(mock::cancelled_handles().len(), 2);
+        assert_eq!(mock::open_handles(), 0);
+    }
+
+    #[test]
+    fn select_hedged_on_no_requests_is_an_error() {
+        guest(|| assert!(matches!(select_hedged(vec![]), Err(SendError::Hostcall))));
+    }
+
+    #[test]
+    fn header_bytes_round_trip_through_upstreams() {
+        mock::set_fake_upstream(FakeUpstream::new().route_any(
+            "http://bin/",
+            Reply::status(200).header("x-bin", b"caf\xe9"),
+        ));
+        let resp = guest(|| {
+            Request::get("http://bin/")
+                .header("x-sent", &b"na\xefve"[..])
+                .body(vec![])
+                .unwrap()
+                .send()
+                .unwrap()
+        });
+        assert_eq!(resp.headers()["x-bin"].as_bytes(), b"caf\xe9");
+        assert_eq!(
+            mock::sent_requests()[0].headers()["x-sent"].as_bytes(),
+            b"na\xefve"
+        );
+    }
+
+    #[test]
+    fn rejected_request_reports_the_host_error() {
+        timed_upstream();
+        let result = guest(|| {
+            let mut req = Request::get("http://fast/").body(vec![]).unwrap();
+            *req.version_mut() = http::Version::HTTP_09;
+            req.send()
+        });
+        match result {
+            Err(SendError::Protocol(_)) => (),
+            other => panic!("expected a protocol error, got {:?}", other),
+        }
+        assert!(mock::sent_requests().is_empty());
+    }
+}
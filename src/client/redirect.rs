@@ -0,0 +1,363 @@
+//! Following redirects from outbound requests.
+
+use http::header;
+use http::{Method, Request, Response, StatusCode, Uri};
+
+use crate::client::{copy_request, RequestExt, SendError};
+
+/// The URIs requested to produce a response, in order.
+///
+/// The first is the URI of the original request, and the last is the one that produced the
+/// response. `RequestExt::send_following_redirects` inserts this into the extensions of the
+/// responses it returns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedirectChain(pub Vec<Uri>);
+
+pub(crate) fn send(req: Request<Vec<u8>>, max_hops: u32) -> Result<Response<Vec<u8>>, SendError> {
+    let mut req = req;
+    let mut chain = vec![req.uri().clone()];
+    loop {
+        let mut resp = copy_request(&req).send()?;
+        let next = match redirect(&req, &resp) {
+            Some(next) => next,
+            None => {
+                resp.extensions_mut().insert(RedirectChain(chain));
+                return Ok(resp);
+            }
+        };
+        if chain.len() > max_hops as usize {
+            return Err(SendError::TooManyRedirects(max_hops));
+        }
+        chain.push(next.uri().clone());
+        req = next;
+    }
+}
+
+/// Build the request that follows `resp`, if it is a redirect that can be followed.
+fn redirect(req: &Request<Vec<u8>>, resp: &Response<Vec<u8>>) -> Option<Request<Vec<u8>>> {
+    let to_get = match resp.status() {
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => req.method() == Method::POST,
+        StatusCode::SEE_OTHER => req.method() != Method::HEAD,
+        StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT => false,
+        _ => return None,
+    };
+    let location = resp.headers().get(header::LOCATION)?.to_str().ok()?;
+    let uri = resolve(req.uri(), location)?;
+
+    let mut next = copy_request(req);
+    if to_get {
+        *next.method_mut() = Method::GET;
+        next.body_mut().clear();
+        let headers = next.headers_mut();
+        headers.remove(header::CONTENT_TYPE);
+        headers.remove(header::CONTENT_LENGTH);
+        headers.remove(header::CONTENT_ENCODING);
+        headers.remove(header::TRANSFER_ENCODING);
+    }
+    if !same_origin(req.uri(), &uri) {
+        next.headers_mut().remove(header::AUTHORIZATION);
+    }
+    *next.uri_mut() = uri;
+    Some(next)
+}
+
+/// Resolve a `Location` header value against the URI that was redirected, as described in
+/// RFC 3986 section 5.2.
+fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    // fragments are never sent, so drop any the location carries
+    let location = location.split('#').next().unwrap_or("").trim();
+    if has_scheme(location) {
+        return location.parse().ok();
+    }
+    let scheme = base.scheme_part()?.as_str();
+    let authority = base.authority_part()?.as_str();
+    let target = if location.starts_with("//") {
+        format!("{}:{}", scheme, location)
+    } else if location.is_empty() {
+        format!("{}://{}{}", scheme, authority, path_and_query(base))
+    } else if location.starts_with('?') {
+        format!("{}://{}{}{}", scheme, authority, base.path(), location)
+    } else {
+        let (path, query) = match location.find('?') {
+            Some(i) => location.split_at(i),
+            None => (location, ""),
+        };
+        let path = if path.starts_with('/') {
+            path.to_owned()
+        } else {
+            // merge with everything up to the last segment of the base path
+            let base_path = base.path();
+            let dir = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+            format!("{}{}", dir, path)
+        };
+        format!(
+            "{}://{}{}{}",
+            scheme,
+            authority,
+            remove_dot_segments(&path),
+            query
+        )
+    };
+    target.parse().ok()
+}
+
+fn path_and_query(uri: &Uri) -> String {
+    match uri.query() {
+        Some(query) => format!("{}?{}", uri.path(), query),
+        None => uri.path().to_owned(),
+    }
+}
+
+/// Whether a URI reference starts with a scheme, making it absolute.
+fn has_scheme(reference: &str) -> bool {
+    match reference.find(':') {
+        Some(i) => {
+            let scheme = &reference[..i];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ['+', '-', '.'].contains(&c))
+        }
+        None => false,
+    }
+}
+
+/// Remove `.` and `..` segments from an absolute path.
+fn remove_dot_segments(path: &str) -> String {
+    let mut out = vec![];
+    let mut segments = path.split('/').skip(1).peekable();
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+        match segment {
+            "." => {}
+            ".." => {
+                out.pop();
+            }
+            _ => {
+                out.push(segment);
+                continue;
+            }
+        }
+        // a path ending in a dot segment names a directory
+        if last {
+            out.push("");
+        }
+    }
+    format!("/{}", out.join("/"))
+}
+
+/// Whether two URIs have the same scheme, host and port.
+fn same_origin(a: &Uri, b: &Uri) -> bool {
+    let origin = |uri: &Uri| {
+        let scheme = uri.scheme_str().map(str::to_ascii_lowercase);
+        let port = uri.port_u16().or_else(|| match scheme {
+            Some(ref s) if s == "http" => Some(80),
+            Some(ref s) if s == "https" => Some(443),
+            _ => None,
+        });
+        (scheme, uri.host().map(str::to_ascii_lowercase), port)
+    };
+    origin(a) == origin(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "mock")]
+    use crate::test_util::redirect_upstream;
+
+    #[test]
+    fn resolve_references() {
+        let base: Uri = "http://a/b/c/d;p?q".parse().unwrap();
+        // the normal and abnormal examples from RFC 3986 section 5.4, and a few more
+        let cases = [
+            ("g:h", "g:h"),
+            ("https://other/x", "https://other/x"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g/"),
+            ("//g/x?y", "http://g/x?y"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q"),
+            ("g#s", "http://a/b/c/g"),
+            (";x", "http://a/b/c/;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("  /g  ", "http://a/g"),
+        ];
+        for &(location, expected) in &cases {
+            assert_eq!(
+                resolve(&base, location).map(|uri| uri.to_string()),
+                Some(expected.to_owned()),
+                "{:?}",
+                location
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_invalid_reference() {
+        let base: Uri = "http://a/b".parse().unwrap();
+        assert_eq!(resolve(&base, "/with space"), None);
+    }
+
+    #[test]
+    fn dot_segments() {
+        let cases = [
+            ("/", "/"),
+            ("/a/b/c", "/a/b/c"),
+            ("/a/./b", "/a/b"),
+            ("/a/../b", "/b"),
+            ("/a/b/..", "/a/"),
+            ("/a/b/.", "/a/b/"),
+            ("/..", "/"),
+            ("/../../a", "/a"),
+            ("/a//b", "/a//b"),
+            ("/a/b/../../..", "/"),
+        ];
+        for &(path, expected) in &cases {
+            assert_eq!(remove_dot_segments(path), expected, "{:?}", path);
+        }
+    }
+
+    fn request(method: Method, uri: &str) -> Request<Vec<u8>> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header(header::AUTHORIZATION, "secret")
+            .body(b"body".to_vec())
+            .unwrap()
+    }
+
+    fn response(status: u16, location: Option<&str>) -> Response<Vec<u8>> {
+        let mut resp = Response::builder();
+        resp.status(status);
+        if let Some(location) = location {
+            resp.header(header::LOCATION, location);
+        }
+        resp.body(vec![]).unwrap()
+    }
+
+    #[test]
+    fn redirect_methods() {
+        // (status, method, method of the next request)
+        let cases = [
+            (301, Method::POST, Method::GET),
+            (301, Method::PUT, Method::PUT),
+            (302, Method::POST, Method::GET),
+            (303, Method::POST, Method::GET),
+            (303, Method::PUT, Method::GET),
+            (303, Method::HEAD, Method::HEAD),
+            (307, Method::POST, Method::POST),
+            (308, Method::POST, Method::POST),
+        ];
+        for (status, method, expected) in cases.iter().cloned() {
+            let req = request(method.clone(), "http://a/form");
+            let next = redirect(&req, &response(status, Some("/done"))).unwrap();
+            assert_eq!(next.method(), expected, "{} {}", status, method);
+            assert_eq!(next.uri(), "http://a/done");
+            if expected == Method::GET && method != Method::GET {
+                assert!(next.body().is_empty());
+                assert!(!next.headers().contains_key(header::CONTENT_TYPE));
+            } else {
+                assert_eq!(next.body(), b"body");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_location_redirects_to_the_same_uri() {
+        let req = request(Method::GET, "http://a/b?q");
+        let next = redirect(&req, &response(302, Some(""))).unwrap();
+        assert_eq!(next.uri(), "http://a/b?q");
+    }
+
+    #[test]
+    fn responses_that_are_not_followed() {
+        let req = request(Method::GET, "http://a/b");
+        assert!(redirect(&req, &response(200, Some("/c"))).is_none());
+        assert!(redirect(&req, &response(304, Some("/c"))).is_none());
+        assert!(redirect(&req, &response(302, None)).is_none());
+    }
+
+    #[test]
+    fn authorization_is_only_kept_for_the_same_origin() {
+        let req = request(Method::GET, "http://a/b");
+        let cases = [
+            ("/c", true),
+            ("http://a:80/c", true),
+            ("HTTP://A/c", true),
+            ("https://a/c", false),
+            ("http://a:8080/c", false),
+            ("//other/c", false),
+        ];
+        for &(location, kept) in &cases {
+            let next = redirect(&req, &response(302, Some(location))).unwrap();
+            assert_eq!(
+                next.headers().contains_key(header::AUTHORIZATION),
+                kept,
+                "{}",
+                location
+            );
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    fn follow(method: Method, uri: &str, max_hops: u32) -> Result<Response<Vec<u8>>, SendError> {
+        crate::mock::guest(|| request(method, uri).send_following_redirects(max_hops))
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn follows_up_to_max_hops() {
+        redirect_upstream();
+        let resp = follow(Method::GET, "http://a/1", 2).unwrap();
+        assert_eq!(resp.body(), b"done");
+        let chain = &resp.extensions().get::<RedirectChain>().unwrap().0;
+        assert_eq!(chain, &["http://a/1", "http://a/2", "http://a/3"]);
+
+        match follow(Method::GET, "http://a/1", 1) {
+            Err(SendError::TooManyRedirects(1)) => (),
+            other => panic!("expected too many redirects, got {:?}", other),
+        }
+        match follow(Method::GET, "http://a/1", 0) {
+            Err(SendError::TooManyRedirects(0)) => (),
+            other => panic!("expected too many redirects, got {:?}", other),
+        }
+        assert_eq!(follow(Method::GET, "http://a/3", 0).unwrap().status(), 200);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn see_other_is_followed_with_get() {
+        redirect_upstream();
+        let resp = follow(Method::POST, "http://a/form", 1).unwrap();
+        assert_eq!(resp.body(), b"done");
+        let sent = crate::mock::sent_requests();
+        assert_eq!(
+            sent.iter()
+                .map(|req| req.method().clone())
+                .collect::<Vec<_>>(),
+            [Method::POST, Method::GET]
+        );
+        assert!(sent[1].body().is_empty());
+    }
+}
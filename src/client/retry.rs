@@ -0,0 +1,437 @@
+//! Retrying outbound requests.
+
+use failure::Fail;
+use http::{Method, Request, Response, StatusCode};
+use rand_core::RngCore;
+use std::fmt;
+use std::time::Duration;
+
+use crate::client::{copy_request, PendingRequest, RequestExt, SendError, Timeouts};
+use crate::rand::guest_rng;
+use crate::time::Time;
+
+/// The number of attempts it took to get a response.
+///
+/// `RetryPolicy` inserts this into the extensions of every response it returns, and includes it
+/// in every `RetryError`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Attempts(pub u32);
+
+impl fmt::Display for Attempts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            1 => write!(f, "1 attempt"),
+            n => write!(f, "{} attempts", n),
+        }
+    }
+}
+
+/// The error from the last attempt of a request sent with a `RetryPolicy`, and how many attempts
+/// were made.
+///
+/// This converts into the `SendError`, so `?` can still be used in a function that returns one.
+#[derive(Debug, Fail)]
+#[fail(display = "{} (after {})", error, attempts)]
+pub struct RetryError {
+    pub attempts: Attempts,
+    #[cause]
+    pub error: SendError,
+}
+
+impl From<RetryError> for SendError {
+    fn from(e: RetryError) -> SendError {
+        e.error
+    }
+}
+
+/// A policy for retrying outbound requests that fail or return a retryable status.
+///
+/// By default, a request is attempted up to 3 times. Connection, DNS, timeout and protocol
+/// failures are retried, as are responses with status 502, 503 or 504. Only requests with
+/// idempotent methods are retried, and retries are spaced by an exponential backoff starting at
+/// 100ms, with full jitter from `rand::guest_rng`.
+///
+/// ```text
+/// let policy = RetryPolicy::new()
+///     .max_attempts(4)
+///     .retry_on_status(&[429, 503])
+///     .deadline(Duration::from_secs(2));
+/// let resp = policy.send(Request::get("https://example.com/").body(vec![])?)?;
+/// let Attempts(n) = resp.extensions().get::<Attempts>().cloned().unwrap();
+/// ```
+///
+/// A request that fails returns a `RetryError` with the error from the last attempt.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    retry_statuses: Vec<StatusCode>,
+    retry_non_idempotent: bool,
+    base_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            retry_statuses: vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_non_idempotent: false,
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// Set the most attempts made for a request, including the first.
+    ///
+    /// A request is always attempted at least once, so a `max_attempts` of zero is treated as one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> RetryPolicy {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the response status codes that cause a retry, replacing the defaults.
+    ///
+    /// Panics if any of the codes is not a valid status code.
+    pub fn retry_on_status(mut self, codes: &[u16]) -> RetryPolicy {
+        self.retry_statuses = codes
+            .iter()
+            .map(|&code| StatusCode::from_u16(code).expect("valid status code"))
+            .collect();
+        self
+    }
+
+    /// Also retry requests whose method is not idempotent, such as `POST`.
+    pub fn retry_non_idempotent(mut self, retry: bool) -> RetryPolicy {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    /// Set the delay before the first retry, and the most that the delay may grow to as it
+    /// doubles with each attempt.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> RetryPolicy {
+        self.base_backoff = base;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Set an overall deadline for all attempts, measured from when the request is first sent.
+    ///
+    /// Each attempt's total timeout is shortened to fit in the time remaining, and no retry is
+    /// made if its backoff would pass the deadline.
+    pub fn deadline(mut self, deadline: Duration) -> RetryPolicy {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Synchronously send a request, retrying according to this policy.
+    ///
+    /// Returns the last response or error once the request succeeds, fails in a way that is
+    /// not retryable, or runs out of attempts or time.
+    pub fn send(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RetryError> {
+        let mut retry = Retry::new(self.clone(), req);
+        loop {
+            let result = retry.next_request().send();
+            if let Some(result) = retry.finish(result) {
+                return result;
+            }
+        }
+    }
+
+    /// Asynchronously send a request, retrying according to this policy when it is waited on.
+    ///
+    /// Returns a `SendError` if the first attempt could not be started.
+    pub fn send_async(&self, req: Request<Vec<u8>>) -> Result<RetryingRequest, SendError> {
+        let mut retry = Retry::new(self.clone(), req);
+        let pending = retry.next_request().send_async()?;
+        Ok(RetryingRequest { retry, pending })
+    }
+}
+
+/// A request sent with `RetryPolicy::send_async`.
+pub struct RetryingRequest {
+    retry: Retry,
+    pending: PendingRequest,
+}
+
+impl RetryingRequest {
+    /// Block until the request has completed, making any retries the policy allows.
+    pub fn wait(self) -> Result<Response<Vec<u8>>, RetryError> {
+        let RetryingRequest { mut retry, pending } = self;
+        let mut result = pending.wait();
+        loop {
+            if let Some(result) = retry.finish(result) {
+                return result;
+            }
+            result = retry
+                .next_request()
+                .send_async()
+                .and_then(PendingRequest::wait);
+        }
+    }
+}
+
+/// The state of a request across its attempts.
+struct Retry {
+    policy: RetryPolicy,
+    template: Request<Vec<u8>>,
+    started: Duration,
+    attempts: u32,
+}
+
+impl Retry {
+    fn new(policy: RetryPolicy, req: Request<Vec<u8>>) -> Retry {
+        Retry {
+            policy,
+            template: copy_request(&req),
+            started: now(),
+            attempts: 0,
+        }
+    }
+
+    /// Build a copy of the original request for the next attempt.
+    fn next_request(&mut self) -> Request<Vec<u8>> {
+        self.attempts += 1;
+        let mut req = copy_request(&self.template);
+        let mut timeouts = req
+            .extensions()
+            .get::<Timeouts>()
+            .cloned()
+            .unwrap_or_default();
+        if let Some(remaining) = self.remaining() {
+            timeouts.total = Some(timeouts.total.map_or(remaining, |t| t.min(remaining)));
+        }
+        req.extensions_mut().insert(timeouts);
+        req
+    }
+
+    /// The time left before the policy's deadline, if it has one.
+    fn remaining(&self) -> Option<Duration> {
+        self.policy.deadline.map(|deadline| {
+            let elapsed = now().checked_sub(self.started).unwrap_or_default();
+            deadline.checked_sub(elapsed).unwrap_or_default()
+        })
+    }
+
+    /// Decide what to do with the result of an attempt.
+    ///
+    /// Returns the result if it should be returned to the caller, or sleeps through the backoff
+    /// and returns `None` if another attempt should be made.
+    fn finish(
+        &mut self,
+        result: Result<Response<Vec<u8>>, SendError>,
+    ) -> Option<Result<Response<Vec<u8>>, RetryError>> {
+        let retryable = match result {
+            Ok(ref resp) => self.policy.retry_statuses.contains(&resp.status()),
+            Err(ref e) => is_transient(e),
+        };
+        let allowed = self.attempts < self.policy.max_attempts
+            && (self.policy.retry_non_idempotent || is_idempotent(self.template.method()));
+        if retryable && allowed {
+            let backoff = self.backoff();
+            let in_time = match self.remaining() {
+                Some(remaining) => backoff < remaining,
+                None => true,
+            };
+            if in_time {
+                Time::sleep(backoff);
+                return None;
+            }
+        }
+        let attempts = Attempts(self.attempts);
+        Some(match result {
+            Ok(mut resp) => {
+                resp.extensions_mut().insert(attempts);
+                Ok(resp)
+            }
+            Err(error) => Err(RetryError { attempts, error }),
+        })
+    }
+
+    /// Pick the delay before the next attempt: a random duration up to the base backoff doubled
+    /// for each attempt so far, capped at the maximum backoff.
+    fn backoff(&self) -> Duration {
+        let exp = self.attempts.saturating_sub(1).min(31);
+        let ceiling = self
+            .policy
+            .base_backoff
+            .checked_mul(1 << exp)
+            .map_or(self.policy.max_backoff, |b| b.min(self.policy.max_backoff));
+        let ceiling_ms = ceiling.as_secs() * 1000 + u64::from(ceiling.subsec_millis());
+        Duration::from_millis(guest_rng().next_u64() % (ceiling_ms + 1))
+    }
+}
+
+fn now() -> Duration {
+    let now = Time::since_epoch();
+    Duration::new(now.as_secs(), now.subsec_nanos())
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    [
+        Method::GET,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::PUT,
+        Method::DELETE,
+    ]
+    .contains(method)
+}
+
+/// Whether a failure might not happen again on another attempt.
+fn is_transient(e: &SendError) -> bool {
+    match *e {
+        SendError::Hostcall
+        | SendError::Dns(_)
+        | SendError::Connect(_)
+        | SendError::Timeout(_)
+        | SendError::Protocol(_) => true,
+        SendError::InvalidUrl(_)
+        | SendError::Tls(_)
+        | SendError::TooManyRedirects(_)
+        | SendError::Http(_) => false,
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::{self, guest, FakeUpstream, Reply};
+    use crate::test_util::{get, ms};
+
+    /// Send `req` with `policy` as `RetryPolicy::send` does, returning the result and the time
+    /// slept before each retry.
+    fn send_timed(
+        policy: RetryPolicy,
+        req: Request<Vec<u8>>,
+    ) -> (Result<Response<Vec<u8>>, RetryError>, Vec<Duration>) {
+        let mut retry = Retry::new(policy, req);
+        let mut sleeps = vec![];
+        loop {
+            let result = retry.next_request().send();
+            let before = now();
+            if let Some(result) = retry.finish(result) {
+                return (result, sleeps);
+            }
+            sleeps.push(now() - before);
+        }
+    }
+
+    #[test]
+    fn zero_max_attempts_still_sends_once() {
+        mock::set_fake_upstream(FakeUpstream::new().route_any("http://up/", Reply::status(503)));
+        let policy = RetryPolicy::new().max_attempts(0);
+        let (result, sleeps) = guest(|| send_timed(policy, get("http://up/")));
+        let resp = result.unwrap();
+        assert_eq!(resp.status(), 503);
+        assert_eq!(resp.extensions().get::<Attempts>(), Some(&Attempts(1)));
+        assert!(sleeps.is_empty());
+    }
+
+    #[test]
+    fn backoff_is_jittered_up_to_an_exponential_ceiling() {
+        mock::set_fake_upstream(FakeUpstream::new().route_any("http://up/", Reply::status(503)));
+        let policy = RetryPolicy::new().max_attempts(5).backoff(ms(100), ms(250));
+        let ceilings = [ms(100), ms(200), ms(250), ms(250)];
+        let mut all_sleeps = vec![];
+        for seed in 0..20 {
+            mock::seed_rng(seed);
+            let (result, sleeps) = guest(|| send_timed(policy.clone(), get("http://up/")));
+            let resp = result.unwrap();
+            assert_eq!(resp.status(), 503);
+            assert_eq!(resp.extensions().get::<Attempts>(), Some(&Attempts(5)));
+            assert_eq!(sleeps.len(), 4);
+            for (sleep, ceiling) in sleeps.iter().zip(&ceilings) {
+                assert!(sleep <= ceiling, "slept {:?}, over {:?}", sleep, ceiling);
+            }
+            all_sleeps.extend(sleeps);
+        }
+        // full jitter spreads the delays out rather than always waiting for the ceiling
+        assert!(all_sleeps.iter().any(|&sleep| sleep < ms(50)));
+        assert!(all_sleeps.iter().any(|&sleep| sleep > ms(150)));
+    }
+
+    #[test]
+    fn backoff_never_exceeds_the_maximum() {
+        let policy = RetryPolicy::new().backoff(ms(1), ms(30));
+        guest(|| {
+            let mut retry = Retry::new(policy, get("http://up/"));
+            for _ in 0..40 {
+                retry.next_request();
+                assert!(retry.backoff() <= ms(30));
+            }
+        });
+    }
+
+    #[test]
+    fn retries_stop_at_the_deadline() {
+        mock::set_fake_upstream(
+            FakeUpstream::new().route_any("http://up/", Reply::failure().latency(ms(300))),
+        );
+        let policy = RetryPolicy::new()
+            .max_attempts(10)
+            .backoff(ms(400), ms(400))
+            .deadline(ms(1000));
+        for seed in 0..20 {
+            mock::seed_rng(seed);
+            let (started, result, finished) = guest(|| {
+                let started = now();
+                let (result, _) = send_timed(policy.clone(), get("http://up/"));
+                (started, result, now())
+            });
+            let err = result.unwrap_err();
+            // the second attempt always fits, since the first backoff is at most 400ms
+            assert!(err.attempts.0 >= 2, "{:?}", err);
+            assert!(
+                finished - started <= ms(1000),
+                "took {:?}",
+                finished - started
+            );
+        }
+    }
+
+    #[test]
+    fn no_retry_once_the_deadline_has_passed() {
+        mock::set_fake_upstream(
+            FakeUpstream::new().route_any("http://up/", Reply::status(503).latency(ms(5))),
+        );
+        let policy = RetryPolicy::new().deadline(ms(5));
+        let (result, sleeps) = guest(|| send_timed(policy, get("http://up/")));
+        assert_eq!(
+            result.unwrap().extensions().get::<Attempts>(),
+            Some(&Attempts(1))
+        );
+        assert!(sleeps.is_empty());
+    }
+
+    #[test]
+    fn errors_carry_the_number_of_attempts() {
+        mock::set_fake_upstream(FakeUpstream::new().route_any("http://up/", Reply::failure()));
+        let policy = RetryPolicy::new().backoff(ms(0), ms(0));
+
+        let err = guest(|| policy.send(get("http://up/"))).unwrap_err();
+        assert_eq!(err.attempts, Attempts(3));
+        assert!(err.to_string().ends_with("(after 3 attempts)"), "{}", err);
+
+        let post = Request::post("http://up/").body(vec![]).unwrap();
+        let err = guest(|| policy.send_async(post).unwrap().wait()).unwrap_err();
+        assert_eq!(err.attempts, Attempts(1));
+        assert!(err.to_string().ends_with("(after 1 attempt)"), "{}", err);
+        match SendError::from(err) {
+            SendError::Connect(ref msg) if msg == "connection refused" => (),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
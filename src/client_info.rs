@@ -0,0 +1,67 @@
+//! Information about the client connection behind the incoming request.
+
+use std::net::SocketAddr;
+
+use crate::hostcalls::RequestHandle;
+
+/// The connection that the incoming request arrived on.
+///
+/// The scaffolding inserts this into the extensions of the request it passes to the user
+/// entrypoint:
+///
+/// ```text
+/// let client = req.extensions().get::<ClientInfo>().unwrap();
+/// if let Some(addr) = client.addr {
+///     rate_limit(addr.ip());
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientInfo {
+    /// The address and port of the client, if the host knows them.
+    pub addr: Option<SocketAddr>,
+    /// The TLS session, if the request was made over TLS.
+    pub tls: Option<TlsInfo>,
+}
+
+/// The TLS session that a request was made over.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsInfo {
+    /// The protocol version, such as `TLSv1.3`.
+    pub protocol: String,
+    /// The name of the negotiated cipher suite.
+    pub cipher: String,
+    /// The server name the client asked for with SNI.
+    pub sni: Option<String>,
+    /// The protocol negotiated through ALPN, such as `h2`.
+    pub alpn: Option<String>,
+}
+
+impl ClientInfo {
+    /// Look up the connection information of the incoming request.
+    pub(crate) fn incoming() -> ClientInfo {
+        let inc = RequestHandle::INCOMING;
+        let protocol = inc.get_tls_protocol();
+        let tls = if protocol.is_empty() {
+            None
+        } else {
+            Some(TlsInfo {
+                protocol,
+                cipher: inc.get_tls_cipher(),
+                sni: non_empty(inc.get_tls_sni()),
+                alpn: non_empty(inc.get_tls_alpn()),
+            })
+        };
+        ClientInfo {
+            addr: inc.get_client_addr(),
+            tls,
+        }
+    }
+}
+
+fn non_empty(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
@@ -0,0 +1,171 @@
+//! Rendering errors returned by handlers as HTTP responses.
+
+use http::{header, Response, StatusCode};
+use std::fmt;
+
+use crate::client::{RetryError, SendError};
+use crate::dns::DNSError;
+
+/// An error that a `guest_app_result` handler can return.
+///
+/// The response sent to the client in place of the handler's is built by `error_response()`. By
+/// default it is a `500 Internal Server Error` with a JSON problem body describing the error, so
+/// an error type only needs an empty implementation:
+///
+/// ```text
+/// #[derive(Debug, Fail)]
+/// #[fail(display = "no such user: {}", _0)]
+/// struct NoSuchUser(String);
+///
+/// impl ErrorResponse for NoSuchUser {
+///     fn error_response(&self) -> Response<Vec<u8>> {
+///         problem_response(StatusCode::NOT_FOUND, self)
+///     }
+/// }
+/// ```
+///
+/// `failure::Error` uses the default, so a handler that returns it can propagate any `Fail` with
+/// `?`, and every error it returns becomes a 500.
+pub trait ErrorResponse: fmt::Display {
+    /// Build the response sent to the client for this error.
+    fn error_response(&self) -> Response<Vec<u8>> {
+        problem_response(StatusCode::INTERNAL_SERVER_ERROR, self)
+    }
+}
+
+/// Failed outbound requests are reported as `504 Gateway Timeout` if they timed out, `502 Bad
+/// Gateway` if the upstream could not be reached or misbehaved, and `500 Internal Server Error`
+/// otherwise.
+impl ErrorResponse for SendError {
+    fn error_response(&self) -> Response<Vec<u8>> {
+        let status = match *self {
+            SendError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            SendError::Dns(_)
+            | SendError::Connect(_)
+            | SendError::Tls(_)
+            | SendError::Protocol(_)
+            | SendError::TooManyRedirects(_) => StatusCode::BAD_GATEWAY,
+            SendError::Hostcall | SendError::InvalidUrl(_) | SendError::Http(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        problem_response(status, self)
+    }
+}
+
+/// Uses the status for the error from the last attempt.
+impl ErrorResponse for RetryError {
+    fn error_response(&self) -> Response<Vec<u8>> {
+        problem_response(self.error.error_response().status(), self)
+    }
+}
+
+impl ErrorResponse for DNSError {}
+
+impl ErrorResponse for failure::Error {}
+
+/// Build a response with the given status and an `application/problem+json` body, as described
+/// in RFC 7807, whose `detail` is the error's message.
+pub fn problem_response<E: fmt::Display + ?Sized>(
+    status: StatusCode,
+    err: &E,
+) -> Response<Vec<u8>> {
+    let body = format!(
+        r#"{{"type":"about:blank","title":"{}","status":{},"detail":"{}"}}"#,
+        json_escape(status.canonical_reason().unwrap_or("")),
+        status.as_u16(),
+        json_escape(&err.to_string()),
+    );
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/problem+json")
+        .body(body.into_bytes())
+        .unwrap()
+}
+
+/// Escape a string for use inside a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(resp: &Response<Vec<u8>>) -> &str {
+        std::str::from_utf8(resp.body()).unwrap()
+    }
+
+    #[test]
+    fn problem_response_body() {
+        let resp = problem_response(StatusCode::NOT_FOUND, "no such user: \"bob\"");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        assert_eq!(
+            body(&resp),
+            r#"{"type":"about:blank","title":"Not Found","status":404,"detail":"no such user: \"bob\""}"#
+        );
+    }
+
+    #[test]
+    fn problem_response_without_reason() {
+        let status = StatusCode::from_u16(599).unwrap();
+        let resp = problem_response(status, "oops");
+        assert_eq!(
+            body(&resp),
+            r#"{"type":"about:blank","title":"","status":599,"detail":"oops"}"#
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let cases = [
+            ("plain", "plain"),
+            ("say \"hi\"", r#"say \"hi\""#),
+            ("C:\\dir", r#"C:\\dir"#),
+            ("a\nb\r\nc\td", r#"a\nb\r\nc\td"#),
+            ("\u{0}\u{8}\u{c}\u{1f}", r#"\u0000\u0008\u000c\u001f"#),
+            ("\u{7f} \u{e9} \u{1f600}", "\u{7f} \u{e9} \u{1f600}"),
+            ("</script>", "</script>"),
+        ];
+        for &(input, expected) in &cases {
+            assert_eq!(json_escape(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn default_error_response() {
+        let err = failure::err_msg("broken \"pipe\"\n");
+        let resp = err.error_response();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body(&resp).ends_with(r#""detail":"broken \"pipe\"\n"}"#));
+    }
+
+    #[test]
+    fn send_error_statuses() {
+        let cases = [
+            (SendError::Timeout(String::new()), 504),
+            (SendError::Connect(String::new()), 502),
+            (SendError::TooManyRedirects(3), 502),
+            (SendError::Hostcall, 500),
+        ];
+        for (err, status) in &cases {
+            assert_eq!(err.error_response().status(), *status, "{:?}", err);
+        }
+    }
+}
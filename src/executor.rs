@@ -0,0 +1,315 @@
+//! A minimal single-threaded executor for `async` guest code.
+//!
+//! `PendingRequest` implements `Future`, so outbound requests can be awaited from an `async`
+//! entrypoint run by `guest_app_async!`, or from any future passed to `block_on`:
+//!
+//! ```text
+//! async fn user_entrypoint(req: Request<Vec<u8>>) -> Response<Vec<u8>> {
+//!     let users = Request::get("https://users/").body(vec![]).unwrap().send_async().unwrap();
+//!     let posts = Request::get("https://posts/").body(vec![]).unwrap().send_async().unwrap();
+//!     let results = executor::join_futures(vec![users, posts]).await;
+//!     ...
+//! }
+//!
+//! guest_app_async!(user_entrypoint);
+//! ```
+//!
+//! When the future being run cannot make progress, the executor blocks in
+//! `hostcalls::select` on every pending request that has been awaited, and wakes the future
+//! that was waiting on whichever completes first. A future that waits on anything else must
+//! arrange to be woken before it returns `Poll::Pending`, or `block_on` panics.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::mem::ManuallyDrop;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::client::SendError;
+use crate::hostcalls::{self, PendingRequestHandle, ResponseHandle};
+
+/// Run a future to completion on the current thread, blocking on the host whenever it is
+/// waiting for a pending request.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let woken = Arc::new(Woken(AtomicBool::new(false)));
+    let waker = Waker::from(woken.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        woken.0.store(false, Ordering::SeqCst);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        while !woken.0.load(Ordering::SeqCst) {
+            reactor::turn();
+        }
+    }
+}
+
+/// Wait for all of `futures` to complete, and return their outputs in the same order.
+///
+/// This joins arbitrary futures; to send a batch of requests with a limit on how many are in
+/// flight, use `client::join_all`.
+pub fn join_futures<F: Future>(futures: Vec<F>) -> JoinFutures<F> {
+    JoinFutures {
+        outputs: futures.iter().map(|_| None).collect(),
+        futures: futures.into_iter().map(Box::pin).collect(),
+    }
+}
+
+/// The future returned by `join_futures`.
+pub struct JoinFutures<F: Future> {
+    futures: Vec<Pin<Box<F>>>,
+    outputs: Vec<Option<F::Output>>,
+}
+
+// the futures are pinned in their own boxes, and the outputs are never pinned
+impl<F: Future> Unpin for JoinFutures<F> {}
+
+impl<F: Future> Future for JoinFutures<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        for (future, output) in this.futures.iter_mut().zip(this.outputs.iter_mut()) {
+            if output.is_none() {
+                if let Poll::Ready(done) = future.as_mut().poll(cx) {
+                    *output = Some(done);
+                }
+            }
+        }
+        if this.outputs.iter().all(Option::is_some) {
+            Poll::Ready(this.outputs.drain(..).map(Option::unwrap).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Records that the future run by `block_on` has been woken.
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Tracks the pending requests that futures are waiting on.
+pub(crate) mod reactor {
+    use super::*;
+
+    #[derive(Default)]
+    struct Reactor {
+        /// Pending requests that have been polled and were not ready, in the order they were
+        /// first polled, with the waker of the future waiting on each.
+        waiting: Vec<(i32, Waker)>,
+        /// Requests that completed in `select` before the future waiting on them was polled.
+        completed: HashMap<i32, Result<ResponseHandle, SendError>>,
+    }
+
+    thread_local! {
+        static REACTOR: RefCell<Reactor> = RefCell::new(Reactor::default());
+    }
+
+    /// Wake the future with `waker` once the pending request `pr` completes.
+    pub(crate) fn register(pr: i32, waker: Waker) {
+        REACTOR.with(|reactor| {
+            let waiting = &mut reactor.borrow_mut().waiting;
+            match waiting.iter_mut().find(|(id, _)| *id == pr) {
+                Some(entry) => entry.1 = waker,
+                None => waiting.push((pr, waker)),
+            }
+        });
+    }
+
+    /// Stop waiting on the pending request `pr`, which has completed without going through
+    /// `turn`.
+    pub(crate) fn deregister(pr: i32) {
+        REACTOR.with(|reactor| reactor.borrow_mut().waiting.retain(|&(id, _)| id != pr));
+    }
+
+    /// Take the result of the pending request `pr`, if it completed while waiting in `turn`.
+    pub(crate) fn take_completed(pr: i32) -> Option<Result<ResponseHandle, SendError>> {
+        REACTOR.with(|reactor| reactor.borrow_mut().completed.remove(&pr))
+    }
+
+    /// Stop tracking the pending request `pr`, which is being consumed or dropped by its owner,
+    /// and return its result if it completed while waiting in `turn`.
+    pub(crate) fn forget(pr: i32) -> Option<Result<ResponseHandle, SendError>> {
+        // a pending request may be dropped while the thread is exiting, after the reactor is gone
+        REACTOR
+            .try_with(|reactor| {
+                let mut reactor = reactor.borrow_mut();
+                reactor.waiting.retain(|&(id, _)| id != pr);
+                reactor.completed.remove(&pr)
+            })
+            .ok()
+            .and_then(|result| result)
+    }
+
+    /// Block until one of the pending requests being waited on completes, and wake the future
+    /// waiting on it. If the host fails without identifying a request, every waiting future is
+    /// woken with the error.
+    pub(crate) fn turn() {
+        let waiting = REACTOR.with(|reactor| {
+            reactor
+                .borrow()
+                .waiting
+                .iter()
+                .map(|&(id, _)| id)
+                .collect::<Vec<i32>>()
+        });
+        if waiting.is_empty() {
+            panic!("future is blocked, but is not waiting on any pending request");
+        }
+        // the futures own these handles, so the copies made here must never close them, even if
+        // `select` panics
+        let handles = waiting
+            .iter()
+            .map(|&id| ManuallyDrop::new(PendingRequestHandle::from(id)))
+            .collect::<Vec<ManuallyDrop<PendingRequestHandle>>>();
+        let completed = match hostcalls::select(&handles.iter().map(|h| &**h).collect::<Vec<_>>()) {
+            Ok((index, resp)) => vec![(waiting[index], Ok(resp))],
+            Err(Some(index)) => vec![(waiting[index], Err(SendError::from_host()))],
+            // the host could not say which request failed, so fail all of them rather than
+            // selecting on the same requests again
+            Err(None) => {
+                let e = SendError::from_host();
+                waiting.iter().map(|&id| (id, Err(e.duplicate()))).collect()
+            }
+        };
+        let wakers = REACTOR.with(|reactor| {
+            let mut reactor = reactor.borrow_mut();
+            completed
+                .into_iter()
+                .filter_map(|(done, result)| {
+                    let pos = reactor.waiting.iter().position(|&(id, _)| id == done)?;
+                    let (_, waker) = reactor.waiting.remove(pos);
+                    reactor.completed.insert(done, result);
+                    Some(waker)
+                })
+                .collect::<Vec<Waker>>()
+        });
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::client::PendingRequest;
+    use crate::mock::{self, guest};
+    use crate::test_util::{send, timed_upstream};
+
+    fn status(result: Result<http::Response<Vec<u8>>, SendError>) -> u16 {
+        result.unwrap().status().as_u16()
+    }
+
+    /// Poll `pr` once, so that the executor starts waiting on it.
+    fn poll_once(pr: &mut PendingRequest) {
+        let waker = Waker::from(Arc::new(Woken(AtomicBool::new(false))));
+        let poll = Pin::new(pr).poll(&mut Context::from_waker(&waker));
+        assert!(poll.is_pending());
+    }
+
+    /// Completes with the output of whichever of two futures is ready first, dropping the other.
+    struct Race<A, B>(A, B);
+
+    impl<A: Future + Unpin, B: Future<Output = A::Output> + Unpin> Future for Race<A, B> {
+        type Output = A::Output;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<A::Output> {
+            if let Poll::Ready(output) = Pin::new(&mut self.0).poll(cx) {
+                return Poll::Ready(output);
+            }
+            Pin::new(&mut self.1).poll(cx)
+        }
+    }
+
+    #[test]
+    fn block_on_awaits_a_request() {
+        timed_upstream();
+        let status = guest(|| block_on(async { status(send("http://slow/").await) }));
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn join_futures_waits_on_requests_together() {
+        timed_upstream();
+        let statuses = guest(|| {
+            let prs = vec![
+                send("http://slower/"),
+                send("http://fast/"),
+                send("http://slow/"),
+            ];
+            let results = block_on(join_futures(prs));
+            assert_eq!(mock::open_handles(), 0);
+            results.into_iter().map(status).collect::<Vec<_>>()
+        });
+        assert_eq!(statuses, [202, 201, 200]);
+    }
+
+    #[test]
+    fn dropped_request_is_no_longer_waited_on() {
+        timed_upstream();
+        let statuses = guest(|| {
+            block_on(async {
+                let first = status(Race(send("http://slow/"), send("http://fast/")).await);
+                // the slow request was dropped while the executor was waiting on it, so it must
+                // not be passed to the host again
+                let next = status(send("http://slower/").await);
+                (first, next)
+            })
+        });
+        assert_eq!(statuses, (201, 202));
+    }
+
+    #[test]
+    fn dropped_request_discards_its_completed_response() {
+        timed_upstream();
+        guest(|| {
+            let mut pr = send("http://fast/");
+            poll_once(&mut pr);
+            reactor::turn();
+            assert_eq!(mock::open_handles(), 1);
+            drop(pr);
+            assert_eq!(mock::open_handles(), 0);
+        });
+    }
+
+    #[test]
+    fn completed_request_can_still_be_waited_on() {
+        timed_upstream();
+        let status = guest(|| {
+            let mut pr = send("http://fast/");
+            poll_once(&mut pr);
+            reactor::turn();
+            status(pr.wait())
+        });
+        assert_eq!(status, 201);
+    }
+
+    #[test]
+    fn host_failure_is_delivered_to_waiting_futures() {
+        timed_upstream();
+        guest(|| {
+            let mut consumed = send("http://fast/");
+            let mut pr = send("http://slow/");
+            poll_once(&mut consumed);
+            poll_once(&mut pr);
+            // completing the request outside the executor leaves a handle the host no longer
+            // knows, so the next select fails without saying which request it failed on
+            crate::client::select(&[&consumed]).unwrap();
+            match block_on(pr) {
+                Err(SendError::Hostcall) => (),
+                other => panic!("expected a hostcall error, got {:?}", other.map(|_| ())),
+            }
+        });
+    }
+}
@@ -0,0 +1,947 @@
+//! Extracting query parameters, forms and multipart bodies from incoming requests.
+//!
+//! `ExtractExt` adds methods to the `Request` passed to a handler for reading its query string,
+//! an `application/x-www-form-urlencoded` body or a `multipart/form-data` body:
+//!
+//! ```text
+//! fn user_entrypoint(req: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+//!     let page = req.query().get("page").unwrap_or("1").to_owned();
+//!     for part in req.multipart().unwrap() {
+//!         let part = part.unwrap();
+//!         if let Some(filename) = part.filename() {
+//!             ...
+//!         }
+//!     }
+//!     ...
+//! }
+//! ```
+//!
+//! A `guest_app_streaming` handler can instead parse a multipart body as it is read from the
+//! host with `Multipart::from_request`, so that only one part is held in memory at a time. Each
+//! part is still read in full, so an uploaded file must fit in memory.
+//!
+//! With the `form` feature, query strings and forms can also be decoded into any type that
+//! implements `serde::Deserialize`.
+
+use failure::Fail;
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Request, Response, StatusCode, Uri};
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use crate::error::{problem_response, ErrorResponse};
+
+/// The largest block of headers accepted at the start of a multipart part.
+const MAX_PART_HEADERS: usize = 16 * 1024;
+
+/// The amount read from the body at a time while parsing a multipart body.
+const READ_CHUNK: usize = 8 * 1024;
+
+#[derive(Debug, Fail)]
+pub enum ExtractError {
+    /// The body did not have the expected `Content-Type`, whose value, if any, is given.
+    #[fail(
+        display = "Unsupported content type: expected {}, got {:?}",
+        expected, found
+    )]
+    ContentType {
+        expected: &'static str,
+        found: String,
+    },
+    /// The `Content-Type` of a multipart body had no `boundary` parameter.
+    #[fail(display = "Multipart content type has no boundary")]
+    MissingBoundary,
+    /// The multipart body was malformed.
+    #[fail(display = "Invalid multipart body: {}", _0)]
+    Multipart(String),
+    /// The body could not be read.
+    #[fail(display = "Error reading body: {}", _0)]
+    Io(#[cause] io::Error),
+    /// The fields did not match the type they were decoded into.
+    #[cfg(feature = "form")]
+    #[fail(display = "Invalid form: {}", _0)]
+    Deserialize(#[cause] serde::de::value::Error),
+}
+
+/// Errors are reported as `415 Unsupported Media Type` if the body was not of the expected type,
+/// `500 Internal Server Error` if it could not be read, and `400 Bad Request` otherwise.
+impl ErrorResponse for ExtractError {
+    fn error_response(&self) -> Response<Vec<u8>> {
+        let status = match *self {
+            ExtractError::ContentType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ExtractError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        problem_response(status, self)
+    }
+}
+
+/// The percent-decoded fields of a query string or `application/x-www-form-urlencoded` body, in
+/// the order they appear.
+///
+/// A name may appear more than once. A field without an `=` has an empty value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FormData(Vec<(String, String)>);
+
+impl FormData {
+    /// Parse a query string or `application/x-www-form-urlencoded` body.
+    ///
+    /// `+` is decoded as a space, and any bytes that are not valid UTF-8 after decoding are
+    /// replaced with `U+FFFD`.
+    pub fn parse(input: &[u8]) -> FormData {
+        FormData(
+            input
+                .split(|&b| b == b'&')
+                .filter(|field| !field.is_empty())
+                .map(|field| {
+                    let mut kv = field.splitn(2, |&b| b == b'=');
+                    let name = kv.next().unwrap_or(b"");
+                    let value = kv.next().unwrap_or(b"");
+                    (decode_form(name), decode_form(value))
+                })
+                .collect(),
+        )
+    }
+
+    /// Parse the query string of `uri`, which is empty if it has none.
+    pub fn from_uri(uri: &Uri) -> FormData {
+        FormData::parse(uri.query().unwrap_or("").as_bytes())
+    }
+
+    /// Get the first value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Iterate over every value of the field `name`.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Iterate over the fields in the order they appear.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Collect the fields into a map, keeping the first value of any field that appears more
+    /// than once.
+    pub fn to_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        for (name, value) in &self.0 {
+            map.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+        map
+    }
+
+    /// Decode the fields into `T`.
+    ///
+    /// Values are parsed into whatever type the field has, such as a number, `bool` or unit enum
+    /// variant. An `Option` field is `None` only if it does not appear, and no field may appear
+    /// more than once.
+    ///
+    /// ```text
+    /// #[derive(Deserialize)]
+    /// struct Search {
+    ///     q: String,
+    ///     page: Option<u32>,
+    /// }
+    ///
+    /// let search: Search = req.query().deserialize()?;
+    /// ```
+    #[cfg(feature = "form")]
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExtractError> {
+        de::from_fields(&self.0).map_err(ExtractError::Deserialize)
+    }
+}
+
+/// Extension trait for extracting data from the `Request` passed to a handler.
+pub trait ExtractExt {
+    /// The fields of the query string.
+    fn query(&self) -> FormData;
+
+    /// The fields of an `application/x-www-form-urlencoded` body.
+    ///
+    /// Returns `ExtractError::ContentType` if the request has any other `Content-Type`.
+    fn form(&self) -> Result<FormData, ExtractError>;
+
+    /// The parts of a `multipart/form-data` body.
+    ///
+    /// Returns `ExtractError::ContentType` if the request has any other `Content-Type`, and
+    /// `ExtractError::MissingBoundary` if it does not give the boundary between parts.
+    fn multipart(&self) -> Result<Multipart<&[u8]>, ExtractError>;
+
+    /// Decode the query string into `T`, as described in `FormData::deserialize`.
+    #[cfg(feature = "form")]
+    fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExtractError> {
+        self.query().deserialize()
+    }
+
+    /// Decode an `application/x-www-form-urlencoded` body into `T`, as described in
+    /// `FormData::deserialize`.
+    #[cfg(feature = "form")]
+    fn form_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExtractError> {
+        self.form()?.deserialize()
+    }
+}
+
+impl ExtractExt for Request<Vec<u8>> {
+    fn query(&self) -> FormData {
+        FormData::from_uri(self.uri())
+    }
+
+    fn form(&self) -> Result<FormData, ExtractError> {
+        check_content_type(self.headers(), "application/x-www-form-urlencoded")?;
+        Ok(FormData::parse(self.body()))
+    }
+
+    fn multipart(&self) -> Result<Multipart<&[u8]>, ExtractError> {
+        let boundary = multipart_boundary(self.headers())?;
+        Ok(Multipart::new(&self.body()[..], &boundary))
+    }
+}
+
+/// One part of a `multipart/form-data` body.
+///
+/// The body of the part, even one that is an uploaded file, is held in memory in full.
+#[derive(Clone, Debug)]
+pub struct Part {
+    headers: HeaderMap,
+    name: Option<String>,
+    filename: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Part {
+    /// The name of the form field, from the `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The name of the uploaded file, if this part is a file.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Whether this part is an uploaded file, rather than the value of a field.
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// The `Content-Type` of the part, if it has one.
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Looking for the first boundary, skipping any preamble.
+    Start,
+    /// Positioned at a boundary that separates parts.
+    Boundary,
+    /// Past the final boundary, or after an error.
+    Done,
+}
+
+/// A parser for `multipart/form-data` bodies, which yields each `Part` in turn.
+///
+/// The body is read as the parts are iterated over, and only the part being parsed is held in
+/// memory, so a streaming request body can be parsed without reading it all first. Each part is
+/// read in full before it is yielded, though, so the largest part, such as an uploaded file,
+/// must fit in memory.
+pub struct Multipart<R> {
+    body: R,
+    /// `CRLF--boundary`, which precedes every part and the end of the body.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    eof: bool,
+    state: State,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Parse `body`, whose parts are separated by `boundary`.
+    pub fn new(body: R, boundary: &str) -> Multipart<R> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        Multipart {
+            body,
+            delimiter,
+            // the first boundary may be at the very start of the body, without a CRLF before it
+            buf: b"\r\n".to_vec(),
+            eof: false,
+            state: State::Start,
+        }
+    }
+
+    /// Parse the body of a `multipart/form-data` request, taking the boundary from its
+    /// `Content-Type`.
+    ///
+    /// ```text
+    /// fn user_entrypoint(req: Request<RequestHandle>) -> Response<Vec<u8>> {
+    ///     for part in Multipart::from_request(req).unwrap() {
+    ///         ...
+    ///     }
+    /// }
+    /// ```
+    pub fn from_request(req: Request<R>) -> Result<Multipart<R>, ExtractError> {
+        let boundary = multipart_boundary(req.headers())?;
+        Ok(Multipart::new(req.into_body(), &boundary))
+    }
+
+    /// Read more of the body into the buffer, returning `false` once it has all been read.
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let len = self.buf.len();
+        self.buf.resize(len + READ_CHUNK, 0);
+        let result = self.body.read(&mut self.buf[len..]);
+        let n = *result.as_ref().unwrap_or(&0);
+        self.buf.truncate(len + n);
+        self.eof = n == 0;
+        result.map(|n| n > 0)
+    }
+
+    /// Read until `needle` is in the buffer at or after `from`, returning its position.
+    fn fill_until(
+        &mut self,
+        needle: &[u8],
+        mut from: usize,
+        limit: Option<usize>,
+    ) -> Result<usize, ExtractError> {
+        loop {
+            if let Some(pos) = find(&self.buf[from..], needle) {
+                return Ok(from + pos);
+            }
+            if matches!(limit, Some(limit) if self.buf.len() > limit) {
+                return Err(ExtractError::Multipart("part headers too long".to_owned()));
+            }
+            from = from.max(self.buf.len().saturating_sub(needle.len() - 1));
+            if !self.fill().map_err(ExtractError::Io)? {
+                return Err(ExtractError::Multipart("unexpected end of body".to_owned()));
+            }
+        }
+    }
+
+    /// Read until the buffer holds at least `n` bytes, or the body ends.
+    fn fill_to(&mut self, n: usize) -> Result<(), ExtractError> {
+        while self.buf.len() < n && self.fill().map_err(ExtractError::Io)? {}
+        Ok(())
+    }
+
+    /// Read until the buffer holds a delimiter that is followed by `--`, or by optional
+    /// whitespace and a CRLF, returning its position. Any other occurrence is part of a body.
+    fn find_delimiter(&mut self) -> Result<usize, ExtractError> {
+        let delimiter = self.delimiter.clone();
+        let mut from = 0;
+        loop {
+            let pos = self.fill_until(&delimiter, from, None)?;
+            let after = pos + delimiter.len();
+            let mut i = after;
+            loop {
+                self.fill_to(i + 2)?;
+                match self.buf.get(i..i + 2) {
+                    Some(b"--") if i == after => return Ok(pos),
+                    Some(b"\r\n") => return Ok(pos),
+                    Some(&[b' ', _]) | Some(&[b'\t', _]) => i += 1,
+                    _ => break,
+                }
+            }
+            from = pos + 1;
+        }
+    }
+
+    fn next_part(&mut self) -> Result<Option<Part>, ExtractError> {
+        if self.state == State::Start {
+            let pos = self.find_delimiter()?;
+            self.buf.drain(..pos);
+            self.state = State::Boundary;
+        }
+        // the buffer starts with the delimiter, followed by `--` after the last part, or by
+        // optional whitespace and a CRLF before the headers of the next
+        self.buf.drain(..self.delimiter.len());
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        let eol = self.fill_until(b"\r\n", 0, None)?;
+        self.buf.drain(..eol + 2);
+
+        // a part with no headers has an empty line straight after the boundary
+        self.fill_to(2)?;
+        let headers = if self.buf.starts_with(b"\r\n") {
+            self.buf.drain(..2);
+            HeaderMap::new()
+        } else {
+            let end = self.fill_until(b"\r\n\r\n", 0, Some(MAX_PART_HEADERS))?;
+            let headers = parse_headers(&self.buf[..end])?;
+            self.buf.drain(..end + 4);
+            headers
+        };
+
+        let end = self.find_delimiter()?;
+        let body = self.buf.drain(..end).collect();
+
+        let (name, filename) = headers
+            .get(header::CONTENT_DISPOSITION)
+            .map(|v| disposition_names(v.as_bytes()))
+            .unwrap_or((None, None));
+        Ok(Some(Part {
+            headers,
+            name,
+            filename,
+            body,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for Multipart<R> {
+    type Item = Result<Part, ExtractError>;
+
+    fn next(&mut self) -> Option<Result<Part, ExtractError>> {
+        if self.state == State::Done {
+            return None;
+        }
+        let result = self.next_part();
+        if result.is_err() {
+            self.state = State::Done;
+        }
+        result.transpose()
+    }
+}
+
+/// Find the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Parse the CRLF-separated header lines at the start of a part.
+fn parse_headers(block: &[u8]) -> Result<HeaderMap, ExtractError> {
+    let mut headers = HeaderMap::new();
+    for line in block.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or_else(|| ExtractError::Multipart("invalid part header".to_owned()))?;
+        let name = HeaderName::from_bytes(&line[..colon])
+            .map_err(|_| ExtractError::Multipart("invalid part header name".to_owned()))?;
+        let value = HeaderValue::from_bytes(trim(&line[colon + 1..]))
+            .map_err(|_| ExtractError::Multipart("invalid part header value".to_owned()))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// The field name and file name from a `Content-Disposition` header, preferring an RFC 5987
+/// `filename*` to a plain `filename`.
+fn disposition_names(value: &[u8]) -> (Option<String>, Option<String>) {
+    let (_, params) = split_params(&String::from_utf8_lossy(value));
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    };
+    let extended = param("filename*").and_then(|v| {
+        // charset'language'value, where only UTF-8 is supported in practice
+        let mut fields = v.splitn(3, '\'');
+        let charset = fields.next()?;
+        let value = fields.nth(1)?;
+        if charset.eq_ignore_ascii_case("utf-8") {
+            Some(String::from_utf8_lossy(&percent_decode(value.as_bytes(), false)).into_owned())
+        } else {
+            None
+        }
+    });
+    (param("name"), extended.or_else(|| param("filename")))
+}
+
+/// Get the boundary of a `multipart/form-data` request from its `Content-Type`.
+fn multipart_boundary(headers: &HeaderMap) -> Result<String, ExtractError> {
+    let params = check_content_type(headers, "multipart/form-data")?;
+    params
+        .into_iter()
+        .find(|(k, _)| k == "boundary")
+        .map(|(_, v)| v)
+        .filter(|v| !v.is_empty())
+        .ok_or(ExtractError::MissingBoundary)
+}
+
+/// Check that the `Content-Type` is `expected`, and return its parameters.
+fn check_content_type(
+    headers: &HeaderMap,
+    expected: &'static str,
+) -> Result<Vec<(String, String)>, ExtractError> {
+    let found = headers
+        .get(header::CONTENT_TYPE)
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        .unwrap_or_default();
+    let (essence, params) = split_params(&found);
+    if essence.eq_ignore_ascii_case(expected) {
+        Ok(params)
+    } else {
+        Err(ExtractError::ContentType { expected, found })
+    }
+}
+
+/// Split a header value such as a media type into its first element and its `;`-separated
+/// parameters, with parameter names lowercased and any quotes removed from values.
+fn split_params(value: &str) -> (String, Vec<(String, String)>) {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = value.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => field.extend(chars.next()),
+            ';' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    let mut fields = fields.into_iter();
+    let first = fields.next().unwrap_or_default().trim().to_owned();
+    let params = fields
+        .filter_map(|field| {
+            let mut kv = field.splitn(2, '=');
+            let key = kv.next()?.trim().to_ascii_lowercase();
+            let value = kv.next().unwrap_or("").trim().to_owned();
+            Some((key, value))
+        })
+        .collect();
+    (first, params)
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|&b| b != b' ' && b != b'\t')
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|&b| b != b' ' && b != b'\t')
+        .map_or(start, |i| i + 1);
+    &bytes[start..end]
+}
+
+/// Decode a name or value from a query string or form.
+fn decode_form(input: &[u8]) -> String {
+    String::from_utf8_lossy(&percent_decode(input, true)).into_owned()
+}
+
+/// Decode `%XX` escapes, and `+` as a space if `plus_as_space` is set. A `%` that does not begin
+/// a valid escape is left as it is.
+pub(crate) fn percent_decode(input: &[u8], plus_as_space: bool) -> Vec<u8> {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let escape = if input[i] == b'%' && i + 2 < input.len() {
+            hex(input[i + 1]).and_then(|hi| hex(input[i + 2]).map(|lo| hi << 4 | lo))
+        } else {
+            None
+        };
+        match escape {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(if plus_as_space && input[i] == b'+' {
+                    b' '
+                } else {
+                    input[i]
+                });
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Decoding fields into `serde` types.
+#[cfg(feature = "form")]
+mod de {
+    use serde::de::value::{Error, MapDeserializer};
+    use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+    use serde::forward_to_deserialize_any;
+
+    pub(super) fn from_fields<T: DeserializeOwned>(
+        fields: &[(String, String)],
+    ) -> Result<T, Error> {
+        T::deserialize(MapDeserializer::new(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_str(), Value(value))),
+        ))
+    }
+
+    /// A field value, which is parsed into the type being deserialized.
+    struct Value<'a>(&'a str);
+
+    impl<'de, 'a> IntoDeserializer<'de, Error> for Value<'a> {
+        type Deserializer = Self;
+
+        fn into_deserializer(self) -> Self {
+            self
+        }
+    }
+
+    macro_rules! parse_value {
+        ($($method:ident => $visit:ident,)*) => {
+            $(
+                fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                    match self.0.parse() {
+                        Ok(v) => visitor.$visit(v),
+                        Err(e) => Err(de::Error::custom(format_args!("{:?}: {}", self.0, e))),
+                    }
+                }
+            )*
+        };
+    }
+
+    impl<'de, 'a> de::Deserializer<'de> for Value<'a> {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            visitor.visit_str(self.0)
+        }
+
+        parse_value! {
+            deserialize_bool => visit_bool,
+            deserialize_i8 => visit_i8,
+            deserialize_i16 => visit_i16,
+            deserialize_i32 => visit_i32,
+            deserialize_i64 => visit_i64,
+            deserialize_u8 => visit_u8,
+            deserialize_u16 => visit_u16,
+            deserialize_u32 => visit_u32,
+            deserialize_u64 => visit_u64,
+            deserialize_f32 => visit_f32,
+            deserialize_f64 => visit_f64,
+            deserialize_char => visit_char,
+        }
+
+        fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            visitor.visit_some(self)
+        }
+
+        fn deserialize_newtype_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            visitor: V,
+        ) -> Result<V::Value, Error> {
+            visitor.visit_newtype_struct(self)
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            name: &'static str,
+            variants: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, Error> {
+            IntoDeserializer::<Error>::into_deserializer(self.0)
+                .deserialize_enum(name, variants, visitor)
+        }
+
+        forward_to_deserialize_any! {
+            i128 u128 str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+            struct identifier ignored_any
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{error_status, post};
+
+    /// Reads `data` at most `chunk` bytes at a time, as a body streamed from the host may be.
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl<'a> Read for Chunked<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    const CHUNK_SIZES: [usize; 7] = [1, 2, 3, 5, 7, 13, READ_CHUNK];
+
+    fn parse(body: &[u8], chunk: usize) -> Result<Vec<Part>, ExtractError> {
+        Multipart::new(Chunked { data: body, chunk }, "XX").collect()
+    }
+
+    #[test]
+    fn part_without_headers() {
+        for &chunk in &CHUNK_SIZES {
+            let parts = parse(b"--XX\r\n\r\nbody\r\n--XX--", chunk).unwrap();
+            assert_eq!(parts.len(), 1, "chunk size {}", chunk);
+            assert!(parts[0].headers().is_empty());
+            assert_eq!(parts[0].body(), b"body");
+        }
+    }
+
+    #[test]
+    fn parts_with_headers() {
+        let body: &[u8] = b"preamble\r\n\
+            --XX\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            Exchange rates\r\n\
+            --XX \t\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"rates.txt\"; \
+            filename*=UTF-8''%E2%82%AC%20rates.txt\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            EUR 1\r\n--XXY 2\r\n\
+            --XX--\r\n\
+            epilogue";
+        for &chunk in &CHUNK_SIZES {
+            let parts = parse(body, chunk).unwrap();
+            assert_eq!(parts.len(), 2, "chunk size {}", chunk);
+            assert_eq!(parts[0].name(), Some("title"));
+            assert!(!parts[0].is_file());
+            assert_eq!(parts[0].body(), b"Exchange rates");
+            assert_eq!(parts[1].name(), Some("file"));
+            assert_eq!(parts[1].filename(), Some("\u{20ac} rates.txt"));
+            assert_eq!(parts[1].content_type(), Some("text/plain"));
+            assert_eq!(parts[1].body(), b"EUR 1\r\n--XXY 2");
+        }
+    }
+
+    #[test]
+    fn truncated_body_is_an_error() {
+        for &chunk in &CHUNK_SIZES {
+            for body in &[&b"--XX\r\n\r\nbody"[..], b"--XX\r\nName: x", b"--XX", b""] {
+                match parse(body, chunk) {
+                    Err(ExtractError::Multipart(_)) => (),
+                    other => panic!("{:?} in chunks of {}: {:?}", body, chunk, other.is_ok()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let cases: &[(&str, bool, &str)] = &[
+            ("plain", true, "plain"),
+            ("%41%62c", true, "Abc"),
+            ("a+b%2Bc", true, "a b+c"),
+            ("a+b", false, "a+b"),
+            ("%e2%82%ac", false, "\u{20ac}"),
+            ("100%", true, "100%"),
+            ("%4", true, "%4"),
+            ("%zz%4g", true, "%zz%4g"),
+            ("%%41", true, "%A"),
+        ];
+        for &(input, plus_as_space, expected) in cases {
+            let decoded = percent_decode(input.as_bytes(), plus_as_space);
+            assert_eq!(String::from_utf8(decoded).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn form_data() {
+        let form = FormData::parse(b"a=1&b=%20x+y&a=2&flag&=empty&c=");
+        assert_eq!(form.get("a"), Some("1"));
+        assert_eq!(form.get_all("a").collect::<Vec<_>>(), ["1", "2"]);
+        assert_eq!(form.get("b"), Some(" x y"));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("c"), Some(""));
+        assert_eq!(form.get("missing"), None);
+    }
+
+    const URI: &str = "/submit?page=2&sort=name&sort=date&q=a+b%21";
+
+    #[test]
+    fn query() {
+        let query = post(URI, None, b"").query();
+        assert_eq!(query.get("page"), Some("2"));
+        assert_eq!(query.get_all("sort").collect::<Vec<_>>(), ["name", "date"]);
+        assert_eq!(query.get("q"), Some("a b!"));
+        let req = Request::get("/").body(vec![]).unwrap();
+        assert!(req.query().is_empty());
+    }
+
+    #[test]
+    fn form_requires_its_content_type() {
+        let form = post(
+            URI,
+            Some("application/x-www-form-urlencoded; charset=utf-8"),
+            b"name=ferris&age=7",
+        )
+        .form()
+        .unwrap();
+        assert_eq!(form.get("name"), Some("ferris"));
+
+        for content_type in &[None, Some("text/plain"), Some("multipart/form-data")] {
+            match post(URI, *content_type, b"name=ferris").form() {
+                Err(e @ ExtractError::ContentType { .. }) => assert_eq!(error_status(e), 415),
+                other => panic!("{:?}: {:?}", content_type, other),
+            }
+        }
+    }
+
+    #[test]
+    fn multipart_requires_its_content_type_and_a_boundary() {
+        let body = b"--XX\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--XX--";
+        let req = post(URI, Some("multipart/form-data; boundary=\"XX\""), body);
+        let parts = req
+            .multipart()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].name(), Some("a"));
+
+        match post(URI, Some("application/json"), body).multipart() {
+            Err(e @ ExtractError::ContentType { .. }) => assert_eq!(error_status(e), 415),
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("parsed a JSON body as multipart"),
+        }
+        for content_type in &["multipart/form-data", "multipart/form-data; boundary="] {
+            match post(URI, Some(content_type), body).multipart() {
+                Err(e @ ExtractError::MissingBoundary) => assert_eq!(error_status(e), 400),
+                Err(e) => panic!("{}: {:?}", content_type, e),
+                Ok(_) => panic!("{}: parsed without a boundary", content_type),
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_multipart_is_a_bad_request() {
+        let req = post(
+            URI,
+            Some("multipart/form-data; boundary=XX"),
+            b"--XX\r\nno colon\r\n\r\n",
+        );
+        let err = req.multipart().unwrap().next().unwrap().unwrap_err();
+        assert_eq!(error_status(err), 400);
+    }
+
+    #[test]
+    fn multipart_from_a_streamed_request() {
+        let body: &[u8] = b"--b0undary\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+            \r\n\
+            contents\r\n\
+            --b0undary--\r\n";
+        let req = Request::post("/upload")
+            .header(
+                header::CONTENT_TYPE,
+                "multipart/form-data; boundary=b0undary",
+            )
+            .body(Chunked {
+                data: body,
+                chunk: 5,
+            })
+            .unwrap();
+        let parts = Multipart::from_request(req)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].is_file());
+        assert_eq!(parts[0].filename(), Some("a.txt"));
+        assert_eq!(parts[0].clone().into_body(), b"contents");
+
+        let req = Request::post("/upload").body(&b""[..]).unwrap();
+        match Multipart::from_request(req) {
+            Err(ExtractError::ContentType { found, .. }) => assert_eq!(found, ""),
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("parsed without a content type"),
+        }
+    }
+
+    #[cfg(feature = "form")]
+    #[test]
+    fn deserialize_fields() {
+        use std::collections::{BTreeMap, HashMap};
+
+        let form = FormData::parse(b"a=1&b=22&c=333");
+        let numbers: BTreeMap<String, u16> = form.deserialize().unwrap();
+        assert_eq!(numbers.values().cloned().collect::<Vec<_>>(), [1, 22, 333]);
+
+        let flags: HashMap<String, bool> =
+            FormData::parse(b"x=true&y=false").deserialize().unwrap();
+        assert!(flags["x"] && !flags["y"]);
+
+        let optional: HashMap<String, Option<f64>> =
+            FormData::parse(b"lat=51.5").deserialize().unwrap();
+        assert_eq!(optional["lat"], Some(51.5));
+
+        let strings: HashMap<String, String> = FormData::parse(b"name=caf%C3%A9+au+lait")
+            .deserialize()
+            .unwrap();
+        assert_eq!(strings["name"], "caf\u{e9} au lait");
+
+        for input in &[&b"a=x"[..], b"a=-1", b"a=70000", b"a="] {
+            match FormData::parse(input).deserialize::<HashMap<String, u16>>() {
+                Err(e @ ExtractError::Deserialize(_)) => assert_eq!(error_status(e), 400),
+                other => panic!("{:?}: {:?}", input, other),
+            }
+        }
+    }
+
+    #[cfg(feature = "form")]
+    #[test]
+    fn query_as_and_form_as() {
+        use std::collections::HashMap;
+
+        let req = post(URI, Some("application/x-www-form-urlencoded"), b"age=7");
+        let query: HashMap<String, String> = req.query_as().unwrap();
+        assert_eq!(query["page"], "2");
+        assert_eq!(query["q"], "a b!");
+        let page: Result<HashMap<String, u32>, _> = req.query_as();
+        assert_eq!(error_status(page.unwrap_err()), 400);
+
+        let form: HashMap<String, u8> = req.form_as().unwrap();
+        assert_eq!(form["age"], 7);
+        let form: Result<HashMap<String, bool>, _> = req.form_as();
+        assert_eq!(error_status(form.unwrap_err()), 400);
+
+        let json = post(URI, Some("application/json"), b"{}");
+        match json.form_as::<HashMap<String, String>>() {
+            Err(e @ ExtractError::ContentType { .. }) => assert_eq!(error_status(e), 415),
+            other => panic!("{:?}", other),
+        }
+    }
+}
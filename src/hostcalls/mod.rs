@@ -13,12 +13,96 @@ pub mod raw;
 pub mod types;
 
 pub use crate::hostcalls::types::{
-    GuestSlice, HostcallStatus, PendingRequestHandle, PollResult, RequestHandle, ResponseHandle,
+    ErrorCode, GuestSlice, HostcallStatus, HttpVersion, PendingRequestHandle, PollResult,
+    RequestHandle, ResponseHandle, ResponseTimings,
 };
 
 use crate::guest_allocator::free;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
 use std::{ptr, slice};
 
+/// Convert a duration to the milliseconds taken by the hostcalls,
+/// rounding up so that short durations are not mistaken for zero.
+pub(crate) fn duration_ms(duration: Duration) -> u64 {
+    let ms = duration.as_secs().saturating_mul(1000) + u64::from(duration.subsec_millis());
+    if Duration::from_millis(ms) < duration {
+        ms.saturating_add(1)
+    } else {
+        ms
+    }
+}
+
+/// Build a socket address from an IP address returned by a hostcall in a 16-byte buffer, of
+/// which the first `addr_len` bytes are used.
+fn socket_addr(addr: [u8; 16], addr_len: usize, port: u16) -> SocketAddr {
+    let ip = match addr_len {
+        4 => {
+            let mut ip = [0u8; 4];
+            ip.copy_from_slice(&addr[..4]);
+            IpAddr::V4(Ipv4Addr::from(ip))
+        }
+        16 => IpAddr::V6(Ipv6Addr::from(addr)),
+        _ => panic!("address returned from host had length {}", addr_len),
+    };
+    SocketAddr::new(ip, port)
+}
+
+/// Convert microseconds returned by a hostcall to a duration, where `u64::MAX` means the
+/// duration is not known.
+fn duration_us(us: u64) -> Option<Duration> {
+    if us == u64::MAX {
+        None
+    } else {
+        Some(Duration::from_micros(us))
+    }
+}
+
+/// Copy a list of byte strings returned by a hostcall out of guest
+/// memory, freeing the memory the host allocated for it.
+fn take_slices(slices_ptr: *mut GuestSlice<u8>, slices_len: usize) -> Vec<Vec<u8>> {
+    if slices_len == 0 {
+        return vec![];
+    }
+    assert!(!slices_ptr.is_null());
+    let slices = unsafe { slice::from_raw_parts_mut(slices_ptr, slices_len) };
+    let mut items = vec![];
+    for s in slices {
+        items.push(unsafe { s.to_slice() }.to_vec());
+        free(s.raw() as _);
+    }
+    free(slices_ptr as _);
+    items
+}
+
+fn lossy_strings(items: Vec<Vec<u8>>) -> Vec<String> {
+    items
+        .iter()
+        .map(|item| String::from_utf8_lossy(item).into_owned())
+        .collect()
+}
+
+/// Copy a string returned by a hostcall out of guest memory, freeing
+/// the memory the host allocated for it.
+fn take_string(ptr: *mut u8, len: usize) -> String {
+    if len == 0 {
+        return String::new();
+    }
+    assert!(!ptr.is_null());
+    let s = unsafe { slice::from_raw_parts_mut(ptr, len) };
+    let s = String::from_utf8_lossy(s).to_string();
+    free(ptr as _);
+    s
+}
+
+fn guest_slices(values: &[&[u8]]) -> Vec<GuestSlice<u8>> {
+    values
+        .iter()
+        .map(|v| GuestSlice::new(v.as_ptr(), v.len()))
+        .collect()
+}
+
 impl RequestHandle {
     /// Create a new request.
     ///
@@ -47,9 +131,7 @@ impl RequestHandle {
     /// Synchronously send a request.
     ///
     /// Consumes the request, and returns a response. If the request fails, this returns
-    /// `None`. Future versions of this API will offer more details about why a failure occurred,
-    /// but the potential reasons are described in the [`reqwest`
-    /// documentation](https://docs.rs/reqwest/0.9.3/reqwest/struct.Error.html).
+    /// `None`, and `last_error()` describes why until the next hostcall.
     ///
     /// It is an error to call this method on `RequestHandle::INCOMING`.
     pub fn send(self) -> Option<ResponseHandle> {
@@ -65,9 +147,7 @@ impl RequestHandle {
     /// Asynchronously send a request.
     ///
     /// Consumes the request, and returns a pending request. If request initialization fails, this
-    /// returns `None`. Future versions of this API will offer more details about why a failure
-    /// occurred, but the potential reasons are described in the [`reqwest`
-    /// documentation](https://docs.rs/reqwest/0.9.3/reqwest/struct.Error.html).
+    /// returns `None`, and `last_error()` describes why until the next hostcall.
     ///
     /// It is an error to call this method on `RequestHandle::INCOMING`.
     pub fn send_async(self) -> Option<PendingRequestHandle> {
@@ -82,9 +162,20 @@ impl RequestHandle {
 
     /// Get the values associated in the request with a particular header name.
     ///
+    /// Values that are not valid UTF-8 are converted lossily; use `get_header_bytes()` to get
+    /// them exactly.
+    ///
     /// It is an error to call this method on a request handle
     /// returned by `RequestHandle::create()`.
     pub fn get_header(&self, name: &str) -> Vec<String> {
+        lossy_strings(self.get_header_bytes(name))
+    }
+
+    /// Get the values associated in the request with a particular header name, as bytes.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_header_bytes(&self, name: &str) -> Vec<Vec<u8>> {
         let name_bytes = name.as_bytes();
         let mut values_ptr: *mut GuestSlice<u8> = ptr::null_mut();
         let mut values_len: usize = 0;
@@ -97,19 +188,7 @@ impl RequestHandle {
                 name_bytes.len(),
             )
         };
-        if values_len == 0 {
-            return vec![];
-        }
-        assert!(!values_ptr.is_null());
-        let values_slices = unsafe { slice::from_raw_parts_mut(values_ptr, values_len) };
-        let mut values = vec![];
-        for value_slice in values_slices {
-            let value = String::from_utf8_lossy(unsafe { value_slice.to_slice() });
-            values.push(value.to_string());
-            free(value_slice.raw() as _);
-        }
-        free(values_ptr as _);
-        values
+        take_slices(values_ptr, values_len)
     }
 
     /// Get the names of all headers in the request.
@@ -120,19 +199,7 @@ impl RequestHandle {
         let mut headers_ptr: *mut GuestSlice<u8> = ptr::null_mut();
         let mut headers_len: usize = 0;
         unsafe { raw::hostcall_req_get_headers(&mut headers_ptr, &mut headers_len, self.into()) };
-        if headers_len == 0 {
-            return vec![];
-        }
-        assert!(!headers_ptr.is_null());
-        let header_slices = unsafe { slice::from_raw_parts_mut(headers_ptr, headers_len) };
-        let mut headers = vec![];
-        for header_slice in header_slices {
-            let header = String::from_utf8_lossy(unsafe { header_slice.to_slice() });
-            headers.push(header.to_string());
-            free(header_slice.raw() as _);
-        }
-        free(headers_ptr as _);
-        headers
+        lossy_strings(take_slices(headers_ptr, headers_len))
     }
 
     /// Get the HTTP method of the request.
@@ -143,18 +210,14 @@ impl RequestHandle {
         let mut method_ptr: *mut u8 = ptr::null_mut();
         let mut method_len: usize = 0;
         unsafe { raw::hostcall_req_get_method(&mut method_ptr, &mut method_len, self.into()) };
-        if method_len == 0 {
-            return String::new();
-        }
-        assert!(!method_ptr.is_null());
-        let method = unsafe { slice::from_raw_parts_mut(method_ptr, method_len) };
-        let method = String::from_utf8_lossy(method).to_string();
-        free(method_ptr as _);
-        method
+        take_string(method_ptr, method_len)
     }
 
     /// Get the body of the request as a vector of bytes.
     ///
+    /// If some of the body has already been read through the `Read` implementation, this returns
+    /// only the rest of it.
+    ///
     /// It is an error to call this method on a request handle
     /// returned by `RequestHandle::create()`.
     pub fn get_body(&self) -> Vec<u8> {
@@ -178,28 +241,147 @@ impl RequestHandle {
         let mut path_ptr: *mut u8 = ptr::null_mut();
         let mut path_len: usize = 0;
         unsafe { raw::hostcall_req_get_path(&mut path_ptr, &mut path_len, self.into()) };
-        if path_len == 0 {
-            return String::new();
+        take_string(path_ptr, path_len)
+    }
+
+    /// Get the scheme of the request, such as `https`.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_scheme(&self) -> String {
+        let mut scheme_ptr: *mut u8 = ptr::null_mut();
+        let mut scheme_len: usize = 0;
+        unsafe { raw::hostcall_req_get_scheme(&mut scheme_ptr, &mut scheme_len, self.into()) };
+        take_string(scheme_ptr, scheme_len)
+    }
+
+    /// Get the authority of the request, taken from the request line or the `Host` header, such
+    /// as `example.com:8443`. This is empty if the client sent neither.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_authority(&self) -> String {
+        let mut authority_ptr: *mut u8 = ptr::null_mut();
+        let mut authority_len: usize = 0;
+        unsafe {
+            raw::hostcall_req_get_authority(&mut authority_ptr, &mut authority_len, self.into())
+        };
+        take_string(authority_ptr, authority_len)
+    }
+
+    /// Get the query string of the request, without the leading `?`. This is empty if the
+    /// request has no query.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_query(&self) -> String {
+        let mut query_ptr: *mut u8 = ptr::null_mut();
+        let mut query_len: usize = 0;
+        unsafe { raw::hostcall_req_get_query(&mut query_ptr, &mut query_len, self.into()) };
+        take_string(query_ptr, query_len)
+    }
+
+    /// Get the HTTP version the request was made with.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_version(&self) -> http::Version {
+        let version = unsafe { raw::hostcall_req_get_version(self.into()) };
+        match HttpVersion::try_from_u32(version) {
+            Some(version) => version.into(),
+            None => panic!(
+                "HTTP version returned from host was out of range: {}",
+                version
+            ),
+        }
+    }
+
+    /// Get the address and port of the client that made the request, if the host knows it.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_client_addr(&self) -> Option<SocketAddr> {
+        let mut addr = [0u8; 16];
+        let mut addr_len: usize = 0;
+        let mut port: u16 = 0;
+        let found = unsafe {
+            raw::hostcall_req_get_client_addr(
+                addr.as_mut_ptr(),
+                &mut addr_len,
+                &mut port,
+                self.into(),
+            )
+        };
+        if found {
+            Some(socket_addr(addr, addr_len, port))
+        } else {
+            None
         }
-        assert!(!path_ptr.is_null());
-        let path = unsafe { slice::from_raw_parts_mut(path_ptr, path_len) };
-        let path = String::from_utf8_lossy(path).to_string();
-        free(path_ptr as _);
-        path
+    }
+
+    /// Get the TLS protocol version the request was made over, such as `TLSv1.3`. This is empty
+    /// if the request was not made over TLS.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_tls_protocol(&self) -> String {
+        let mut protocol_ptr: *mut u8 = ptr::null_mut();
+        let mut protocol_len: usize = 0;
+        unsafe {
+            raw::hostcall_req_get_tls_protocol(&mut protocol_ptr, &mut protocol_len, self.into())
+        };
+        take_string(protocol_ptr, protocol_len)
+    }
+
+    /// Get the name of the TLS cipher suite negotiated with the client. This is empty if the
+    /// request was not made over TLS.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_tls_cipher(&self) -> String {
+        let mut cipher_ptr: *mut u8 = ptr::null_mut();
+        let mut cipher_len: usize = 0;
+        unsafe { raw::hostcall_req_get_tls_cipher(&mut cipher_ptr, &mut cipher_len, self.into()) };
+        take_string(cipher_ptr, cipher_len)
+    }
+
+    /// Get the server name the client asked for with SNI. This is empty if it sent none.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_tls_sni(&self) -> String {
+        let mut sni_ptr: *mut u8 = ptr::null_mut();
+        let mut sni_len: usize = 0;
+        unsafe { raw::hostcall_req_get_tls_sni(&mut sni_ptr, &mut sni_len, self.into()) };
+        take_string(sni_ptr, sni_len)
+    }
+
+    /// Get the protocol negotiated with the client through ALPN, such as `h2`. This is empty if
+    /// none was negotiated.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_tls_alpn(&self) -> String {
+        let mut alpn_ptr: *mut u8 = ptr::null_mut();
+        let mut alpn_len: usize = 0;
+        unsafe { raw::hostcall_req_get_tls_alpn(&mut alpn_ptr, &mut alpn_len, self.into()) };
+        take_string(alpn_ptr, alpn_len)
     }
 
     /// Set a header to potentially-many values in the response.
     ///
     /// It is an error to call this method on `RequestHandle::INCOMING`.
     pub fn set_header(&mut self, name: &str, values: &[&str]) -> HostcallStatus {
+        let values = values.iter().map(|v| v.as_bytes()).collect::<Vec<&[u8]>>();
+        self.set_header_bytes(name, &values)
+    }
+
+    /// Set a header to potentially-many values in the request, given as bytes.
+    ///
+    /// It is an error to call this method on `RequestHandle::INCOMING`.
+    pub fn set_header_bytes(&mut self, name: &str, values: &[&[u8]]) -> HostcallStatus {
         let name_bytes = name.as_bytes();
-        let value_slices: Vec<GuestSlice<u8>> = values
-            .iter()
-            .map(|v| {
-                let v_bytes = v.as_bytes();
-                GuestSlice::new(v_bytes.as_ptr(), v_bytes.len())
-            })
-            .collect();
+        let value_slices = guest_slices(values);
         unsafe {
             raw::hostcall_req_set_header(
                 self.into(),
@@ -211,12 +393,97 @@ impl RequestHandle {
         }
     }
 
+    /// Get the names of all trailers in the request.
+    ///
+    /// Trailers follow the body, so when the body is read in chunks, they are only available
+    /// once all of it has been read.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_trailers(&self) -> Vec<String> {
+        let mut trailers_ptr: *mut GuestSlice<u8> = ptr::null_mut();
+        let mut trailers_len: usize = 0;
+        unsafe {
+            raw::hostcall_req_get_trailers(&mut trailers_ptr, &mut trailers_len, self.into())
+        };
+        lossy_strings(take_slices(trailers_ptr, trailers_len))
+    }
+
+    /// Get the values associated in the request with a particular trailer name, as bytes.
+    ///
+    /// It is an error to call this method on a request handle
+    /// returned by `RequestHandle::create()`.
+    pub fn get_trailer_bytes(&self, name: &str) -> Vec<Vec<u8>> {
+        let name_bytes = name.as_bytes();
+        let mut values_ptr: *mut GuestSlice<u8> = ptr::null_mut();
+        let mut values_len: usize = 0;
+        unsafe {
+            raw::hostcall_req_get_trailer(
+                &mut values_ptr,
+                &mut values_len,
+                self.into(),
+                name_bytes.as_ptr(),
+                name_bytes.len(),
+            )
+        };
+        take_slices(values_ptr, values_len)
+    }
+
+    /// Set a trailer to potentially-many values in the request, given as bytes.
+    ///
+    /// Unlike headers, trailers can still be set after the body has been written in chunks.
+    ///
+    /// It is an error to call this method on `RequestHandle::INCOMING`.
+    pub fn set_trailer_bytes(&mut self, name: &str, values: &[&[u8]]) -> HostcallStatus {
+        let name_bytes = name.as_bytes();
+        let value_slices = guest_slices(values);
+        unsafe {
+            raw::hostcall_req_set_trailer(
+                self.into(),
+                name_bytes.as_ptr(),
+                name_bytes.len(),
+                value_slices.as_ptr(),
+                value_slices.len(),
+            )
+        }
+    }
+
     /// Set the body of the response.
     ///
     /// It is an error to call this method on `RequestHandle::INCOMING`.
     pub fn set_body(&mut self, body: &[u8]) -> HostcallStatus {
         unsafe { raw::hostcall_req_set_body(self.into(), body.as_ptr(), body.len()) }
     }
+
+    /// Set the HTTP version to use for the request.
+    ///
+    /// HTTP/1.0 and HTTP/1.1 are sent as such, and HTTP/2 is used with prior knowledge, without
+    /// upgrading from HTTP/1.1. Returns `HostcallStatus::Invalid` for HTTP/0.9, which the host
+    /// cannot send.
+    ///
+    /// It is an error to call this method on `RequestHandle::INCOMING`.
+    pub fn set_version(&mut self, version: http::Version) -> HostcallStatus {
+        let version = HttpVersion::from(version) as u32;
+        unsafe { raw::hostcall_req_set_version(self.into(), version) }
+    }
+
+    /// Limit how long the request may take to connect, and to complete
+    /// in total. `None` leaves that phase unlimited.
+    ///
+    /// A request that exceeds either limit fails with
+    /// `ErrorCode::Timeout`.
+    ///
+    /// It is an error to call this method on `RequestHandle::INCOMING`.
+    pub fn set_timeouts(
+        &mut self,
+        connect: Option<Duration>,
+        total: Option<Duration>,
+    ) -> HostcallStatus {
+        // zero means no limit, so a limit always rounds up to at least 1ms
+        let connect_ms = connect.map_or(0, |t| duration_ms(t).max(1));
+        let total_ms = total.map_or(0, |t| duration_ms(t).max(1));
+        unsafe { raw::hostcall_req_set_timeouts(self.into(), connect_ms, total_ms) }
+    }
 }
 
 impl ResponseHandle {
@@ -227,25 +494,23 @@ impl ResponseHandle {
         let mut headers_ptr: *mut GuestSlice<u8> = ptr::null_mut();
         let mut headers_len: usize = 0;
         unsafe { raw::hostcall_resp_get_headers(&mut headers_ptr, &mut headers_len, self.into()) };
-        if headers_len == 0 {
-            return vec![];
-        }
-        assert!(!headers_ptr.is_null());
-        let header_slices = unsafe { slice::from_raw_parts_mut(headers_ptr, headers_len) };
-        let mut headers = vec![];
-        for header_slice in header_slices {
-            let header = String::from_utf8_lossy(unsafe { header_slice.to_slice() });
-            headers.push(header.to_string());
-            free(header_slice.raw() as _);
-        }
-        free(headers_ptr as _);
-        headers
+        lossy_strings(take_slices(headers_ptr, headers_len))
     }
 
     /// Get the values associated in the response with a particular header name.
     ///
+    /// Values that are not valid UTF-8 are converted lossily; use `get_header_bytes()` to get
+    /// them exactly.
+    ///
     /// It is an error to call this method on `ResponseHandle::OUTGOING`.
     pub fn get_header(&self, name: &str) -> Vec<String> {
+        lossy_strings(self.get_header_bytes(name))
+    }
+
+    /// Get the values associated in the response with a particular header name, as bytes.
+    ///
+    /// It is an error to call this method on `ResponseHandle::OUTGOING`.
+    pub fn get_header_bytes(&self, name: &str) -> Vec<Vec<u8>> {
         let name_bytes = name.as_bytes();
         let mut values_ptr: *mut GuestSlice<u8> = ptr::null_mut();
         let mut values_len: usize = 0;
@@ -258,23 +523,14 @@ impl ResponseHandle {
                 name_bytes.len(),
             )
         };
-        if values_len == 0 {
-            return vec![];
-        }
-        assert!(!values_ptr.is_null());
-        let values_slices = unsafe { slice::from_raw_parts_mut(values_ptr, values_len) };
-        let mut values = vec![];
-        for value_slice in values_slices {
-            let value = String::from_utf8_lossy(unsafe { value_slice.to_slice() });
-            values.push(value.to_string());
-            free(value_slice.raw() as _);
-        }
-        free(values_ptr as _);
-        values
+        take_slices(values_ptr, values_len)
     }
 
     /// Get the body of the response as a vector of bytes.
     ///
+    /// If some of the body has already been read through the `Read` implementation, this returns
+    /// only the rest of it.
+    ///
     /// It is an error to call this method on `ResponseHandle::OUTGOING`.
     pub fn get_body(&self) -> Vec<u8> {
         let mut body_ptr: *mut u8 = ptr::null_mut();
@@ -304,8 +560,89 @@ impl ResponseHandle {
         }
     }
 
+    /// Get the HTTP version that the upstream responded with.
+    ///
+    /// It is an error to call this method on `ResponseHandle::OUTGOING`.
+    pub fn get_version(&self) -> http::Version {
+        let version = unsafe { raw::hostcall_resp_get_version(self.into()) };
+        match HttpVersion::try_from_u32(version) {
+            Some(version) => version.into(),
+            None => panic!(
+                "HTTP version returned from host was out of range: {}",
+                version
+            ),
+        }
+    }
+
+    /// Get how long each phase of the request that produced this response took.
+    ///
+    /// It is an error to call this method on `ResponseHandle::OUTGOING`.
+    pub fn get_timings(&self) -> ResponseTimings {
+        let mut dns_us = u64::MAX;
+        let mut connect_us = u64::MAX;
+        let mut tls_us = u64::MAX;
+        let mut first_byte_us = u64::MAX;
+        let mut total_us = u64::MAX;
+        unsafe {
+            raw::hostcall_resp_get_timings(
+                &mut dns_us,
+                &mut connect_us,
+                &mut tls_us,
+                &mut first_byte_us,
+                &mut total_us,
+                self.into(),
+            )
+        };
+        ResponseTimings {
+            dns: duration_us(dns_us),
+            connect: duration_us(connect_us),
+            tls: duration_us(tls_us),
+            first_byte: duration_us(first_byte_us),
+            total: duration_us(total_us),
+        }
+    }
+
+    /// Get the address and port of the upstream that sent the response, if the host knows it.
+    ///
+    /// It is an error to call this method on `ResponseHandle::OUTGOING`.
+    pub fn get_remote_addr(&self) -> Option<SocketAddr> {
+        let mut addr = [0u8; 16];
+        let mut addr_len: usize = 0;
+        let mut port: u16 = 0;
+        let found = unsafe {
+            raw::hostcall_resp_get_remote_addr(
+                addr.as_mut_ptr(),
+                &mut addr_len,
+                &mut port,
+                self.into(),
+            )
+        };
+        if found {
+            Some(socket_addr(addr, addr_len, port))
+        } else {
+            None
+        }
+    }
+
+    /// Get the TLS protocol version of the connection the response arrived on, such as
+    /// `TLSv1.3`. This is empty if the connection did not use TLS.
+    ///
+    /// It is an error to call this method on `ResponseHandle::OUTGOING`.
+    pub fn get_tls_protocol(&self) -> String {
+        let mut protocol_ptr: *mut u8 = ptr::null_mut();
+        let mut protocol_len: usize = 0;
+        unsafe {
+            raw::hostcall_resp_get_tls_protocol(&mut protocol_ptr, &mut protocol_len, self.into())
+        };
+        take_string(protocol_ptr, protocol_len)
+    }
+
     /// Set a header to potentially-many values in the response.
     ///
+    /// Once any of the body has been written through the `Write` implementation, the headers have
+    /// been sent, and this returns `HostcallStatus::Invalid`. The same goes for `set_body()` and
+    /// `set_response_code()`.
+    ///
     /// Note: we're considering adding a variant of this that takes a
     /// `Vec<String>` rather than the somewhat awkward (but efficient)
     /// `&[&str]`.
@@ -313,14 +650,17 @@ impl ResponseHandle {
     /// It is an error to call this method on a response handle
     /// returned by `RequestHandle::send()`.
     pub fn set_header(&mut self, name: &str, values: &[&str]) -> HostcallStatus {
+        let values = values.iter().map(|v| v.as_bytes()).collect::<Vec<&[u8]>>();
+        self.set_header_bytes(name, &values)
+    }
+
+    /// Set a header to potentially-many values in the response, given as bytes.
+    ///
+    /// It is an error to call this method on a response handle
+    /// returned by `RequestHandle::send()`.
+    pub fn set_header_bytes(&mut self, name: &str, values: &[&[u8]]) -> HostcallStatus {
         let name_bytes = name.as_bytes();
-        let value_slices: Vec<GuestSlice<u8>> = values
-            .iter()
-            .map(|v| {
-                let v_bytes = v.as_bytes();
-                GuestSlice::new(v_bytes.as_ptr(), v_bytes.len())
-            })
-            .collect();
+        let value_slices = guest_slices(values);
         unsafe {
             raw::hostcall_resp_set_header(
                 self.into(),
@@ -332,6 +672,60 @@ impl ResponseHandle {
         }
     }
 
+    /// Get the names of all trailers in the response.
+    ///
+    /// Trailers follow the body, so when the body is read in chunks, they are only available
+    /// once all of it has been read.
+    ///
+    /// It is an error to call this method on `ResponseHandle::OUTGOING`.
+    pub fn get_trailers(&self) -> Vec<String> {
+        let mut trailers_ptr: *mut GuestSlice<u8> = ptr::null_mut();
+        let mut trailers_len: usize = 0;
+        unsafe {
+            raw::hostcall_resp_get_trailers(&mut trailers_ptr, &mut trailers_len, self.into())
+        };
+        lossy_strings(take_slices(trailers_ptr, trailers_len))
+    }
+
+    /// Get the values associated in the response with a particular trailer name, as bytes.
+    ///
+    /// It is an error to call this method on `ResponseHandle::OUTGOING`.
+    pub fn get_trailer_bytes(&self, name: &str) -> Vec<Vec<u8>> {
+        let name_bytes = name.as_bytes();
+        let mut values_ptr: *mut GuestSlice<u8> = ptr::null_mut();
+        let mut values_len: usize = 0;
+        unsafe {
+            raw::hostcall_resp_get_trailer(
+                &mut values_ptr,
+                &mut values_len,
+                self.into(),
+                name_bytes.as_ptr(),
+                name_bytes.len(),
+            )
+        };
+        take_slices(values_ptr, values_len)
+    }
+
+    /// Set a trailer to potentially-many values in the response, given as bytes.
+    ///
+    /// Unlike headers, trailers can still be set after the body has been written in chunks.
+    ///
+    /// It is an error to call this method on a response handle
+    /// returned by `RequestHandle::send()`.
+    pub fn set_trailer_bytes(&mut self, name: &str, values: &[&[u8]]) -> HostcallStatus {
+        let name_bytes = name.as_bytes();
+        let value_slices = guest_slices(values);
+        unsafe {
+            raw::hostcall_resp_set_trailer(
+                self.into(),
+                name_bytes.as_ptr(),
+                name_bytes.len(),
+                value_slices.as_ptr(),
+                value_slices.len(),
+            )
+        }
+    }
+
     /// Set the body of the response.
     ///
     /// It is an error to call this method on a response handle
@@ -347,15 +741,139 @@ impl ResponseHandle {
     pub fn set_response_code(&mut self, code: u16) -> HostcallStatus {
         unsafe { raw::hostcall_resp_set_response_code(self.into(), code) }
     }
+
+    /// Set the HTTP version of the response.
+    ///
+    /// The host may send a different version if the client cannot accept this one.
+    ///
+    /// It is an error to call this method on a response handle
+    /// returned by `RequestHandle::send()`.
+    pub fn set_version(&mut self, version: http::Version) -> HostcallStatus {
+        let version = HttpVersion::from(version) as u32;
+        unsafe { raw::hostcall_resp_set_version(self.into(), version) }
+    }
+
+    /// Set the reason phrase sent after the status code, in place of the standard one.
+    ///
+    /// HTTP/2 has no reason phrases, so this has no effect on responses sent over it.
+    ///
+    /// It is an error to call this method on a response handle
+    /// returned by `RequestHandle::send()`.
+    pub fn set_reason(&mut self, reason: &str) -> HostcallStatus {
+        let reason_bytes = reason.as_bytes();
+        unsafe {
+            raw::hostcall_resp_set_reason(self.into(), reason_bytes.as_ptr(), reason_bytes.len())
+        }
+    }
+}
+
+/// Reads the body of the incoming request, or of an outbound request that has not been sent,
+/// in chunks rather than all at once.
+impl Read for RequestHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut nread = 0;
+        let status = unsafe {
+            raw::hostcall_req_body_read(self.into(), buf.as_mut_ptr(), buf.len(), &mut nread)
+        };
+        match status {
+            HostcallStatus::Ok => Ok(nread),
+            HostcallStatus::Invalid => Err(body_error("request body could not be read")),
+        }
+    }
+}
+
+/// Appends to the body of an outbound request.
+///
+/// It is an error to write to `RequestHandle::INCOMING`.
+impl Write for RequestHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let status = unsafe { raw::hostcall_req_body_write(self.into(), buf.as_ptr(), buf.len()) };
+        match status {
+            HostcallStatus::Ok => Ok(buf.len()),
+            HostcallStatus::Invalid => Err(body_error("request body could not be written")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the body of a response returned by `RequestHandle::send()` in chunks as it arrives,
+/// rather than all at once.
+impl Read for ResponseHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut nread = 0;
+        let status = unsafe {
+            raw::hostcall_resp_body_read(self.into(), buf.as_mut_ptr(), buf.len(), &mut nread)
+        };
+        match status {
+            HostcallStatus::Ok => Ok(nread),
+            HostcallStatus::Invalid => Err(body_error("response body could not be read")),
+        }
+    }
+}
+
+/// Streams the body of `ResponseHandle::OUTGOING` to the client.
+///
+/// The status code and headers are sent along with the first write, so they must be set
+/// beforehand.
+impl Write for ResponseHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let status = unsafe { raw::hostcall_resp_body_write(self.into(), buf.as_ptr(), buf.len()) };
+        match status {
+            HostcallStatus::Ok => Ok(buf.len()),
+            HostcallStatus::Invalid => Err(body_error("response body could not be written")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[allow(clippy::io_other_error)]
+fn body_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+// Handles at or below zero are the incoming request, the outgoing response, or sentinels, none of
+// which the guest owns. Every other handle has exactly one owner, which closes it once: handles
+// passed to a hostcall that consumes them are converted into an `i32` first, and a pending request
+// that completes in `select()` stays with the caller, which closes it when it is dropped.
+
+impl Drop for RequestHandle {
+    fn drop(&mut self) {
+        let handle = i32::from(&*self);
+        if handle > 0 {
+            unsafe { raw::hostcall_req_close(handle) }
+        }
+    }
+}
+
+impl Drop for ResponseHandle {
+    fn drop(&mut self) {
+        let handle = i32::from(&*self);
+        if handle > 0 {
+            unsafe { raw::hostcall_resp_close(handle) }
+        }
+    }
+}
+
+impl Drop for PendingRequestHandle {
+    fn drop(&mut self) {
+        let handle = i32::from(&*self);
+        if handle > 0 {
+            unsafe { raw::hostcall_pending_req_close(handle) }
+        }
+    }
 }
 
 impl PendingRequestHandle {
     /// Block until the request has completed.
     ///
     /// Consumes the pending request handle, and returns a response. If the request fails, this
-    /// returns `None`. Future versions of this API will offer more details about why a failure
-    /// occurred, but the potential reasons are described in the [`reqwest`
-    /// documentation](https://docs.rs/reqwest/0.9.3/reqwest/struct.Error.html).
+    /// returns `None`, and `last_error()` describes why until the next hostcall.
     pub fn wait(self) -> Option<ResponseHandle> {
         let resp = unsafe { raw::hostcall_pending_req_wait(self.into()) };
         let resp = ResponseHandle::from(resp);
@@ -366,6 +884,24 @@ impl PendingRequestHandle {
         }
     }
 
+    /// Block until the request has completed, or until `timeout` has passed.
+    ///
+    /// If the timeout passes first, returns `PollResult::NotReady(pending_req)`, so that the
+    /// pending request can be used again. Otherwise this behaves like `poll()` on a completed
+    /// request.
+    pub fn wait_timeout(self, timeout: Duration) -> PollResult {
+        let pr_raw = self.into();
+        let resp = unsafe { raw::hostcall_pending_req_wait_timeout(pr_raw, duration_ms(timeout)) };
+        let resp = ResponseHandle::from(resp);
+        if resp.is_error() {
+            PollResult::Error
+        } else if resp.is_not_ready() {
+            PollResult::NotReady(PendingRequestHandle::from(pr_raw))
+        } else {
+            PollResult::Response(resp)
+        }
+    }
+
     /// Poll the status of the pending request without blocking.
     ///
     /// If the request has not completed, returns `PollResult::NotReady(pending_req)`, so that the
@@ -374,9 +910,8 @@ impl PendingRequestHandle {
     /// If the request has completed, consumes the pending request handle, and returns a response in
     /// `PollResult::Response(resp)`.
     ///
-    /// If the request fails, returns `PollResult::Error`. Future versions of this API will offer
-    /// more details about why a failure occurred, but the potential reasons are described in the
-    /// [`reqwest` documentation](https://docs.rs/reqwest/0.9.3/reqwest/struct.Error.html).
+    /// If the request fails, returns `PollResult::Error`, and `last_error()` describes why until
+    /// the next hostcall.
     pub fn poll(self) -> PollResult {
         let pr_raw = self.into();
         let resp = unsafe { raw::hostcall_pending_req_poll(pr_raw) };
@@ -389,38 +924,103 @@ impl PendingRequestHandle {
             PollResult::Response(resp)
         }
     }
+
+    /// Abort the request, closing its connection to the upstream.
+    ///
+    /// Consumes the pending request handle. Returns `HostcallStatus::Invalid` if the request had
+    /// already completed, in which case its response is discarded.
+    pub fn cancel(self) -> HostcallStatus {
+        unsafe { raw::hostcall_pending_req_cancel(self.into()) }
+    }
 }
 
 /// Select from a list of pending requests, blocking until one completes.
 ///
-/// If a request succeeds, returns `Ok((pending_req, resp))` with the request that succeeded,
-/// paired with its response.
+/// If a request succeeds, returns `Ok((index, resp))` with the position in `prs` of the request
+/// that succeeded, paired with its response.
 ///
-/// If a request fails, returns `Err(pending_req)` with the request that failed. Future versions
-/// of this API will offer more details about why a failure occurred, but the potential reasons
-/// are described in the [`reqwest`
-/// documentation](https://docs.rs/reqwest/0.9.3/reqwest/struct.Error.html).
+/// If a request fails, returns `Err(Some(index))` with the position in `prs` of the request that
+/// failed, and `last_error()` describes why until the next hostcall. If the host fails without
+/// identifying one of `prs`, returns `Err(None)`.
 ///
-/// **Note**: the `pending_req` value returned in both the success and error case is no longer
-/// valid as an argument to `wait`, `poll`, or `select`; it is only returned in order to allow
-/// identifying which request succeeded or errored.
+/// **Note**: the pending request at `index` is no longer valid as an argument to `wait`, `poll`,
+/// or `select`, and should be dropped.
 ///
 /// All other pending requests passed to this function remain valid for subsequent calls.
-pub fn select(
-    prs: &[&PendingRequestHandle],
-) -> Result<(PendingRequestHandle, ResponseHandle), PendingRequestHandle> {
+pub fn select(prs: &[&PendingRequestHandle]) -> Result<(usize, ResponseHandle), Option<usize>> {
     let prs = prs.iter().map(|&pr| i32::from(pr)).collect::<Vec<i32>>();
-    let pr_out = Box::into_raw(Box::new(0));
+    let mut pr_out = 0;
 
-    let resp = unsafe { raw::hostcall_pending_req_select(prs.as_ptr(), prs.len(), pr_out) };
+    let resp = unsafe { raw::hostcall_pending_req_select(prs.as_ptr(), prs.len(), &mut pr_out) };
     let resp = ResponseHandle::from(resp);
-    let pr_out = unsafe { PendingRequestHandle::from(*Box::from_raw(pr_out)) };
+    let index = prs.iter().position(|&pr| pr == pr_out);
     if resp.is_error() {
-        Err(pr_out)
+        Err(index)
     } else if resp.is_not_ready() {
         panic!("`select` should never return ResponseHandle::NOT_READY")
     } else {
-        Ok((pr_out, resp))
+        index.map(|index| (index, resp)).ok_or(None)
+    }
+}
+
+/// Get the details of the most recent failure to create, send, or
+/// complete a request.
+///
+/// Returns `None` if the host has not reported a failure. The host
+/// keeps a single last error for the whole instance, not one per
+/// handle, and any later hostcall may replace it, so this must be
+/// called immediately after the hostcall that failed, before any other
+/// hostcall. That includes the hostcalls that close a handle when it is
+/// dropped.
+pub fn last_error() -> Option<(ErrorCode, String)> {
+    let mut msg_ptr: *mut u8 = ptr::null_mut();
+    let mut msg_len: usize = 0;
+    let code = unsafe { raw::hostcall_last_error(&mut msg_ptr, &mut msg_len) };
+    let msg = if msg_len == 0 {
+        String::new()
+    } else {
+        assert!(!msg_ptr.is_null());
+        let msg = unsafe { slice::from_raw_parts_mut(msg_ptr, msg_len) };
+        let msg = String::from_utf8_lossy(msg).to_string();
+        free(msg_ptr as _);
+        msg
+    };
+    match ErrorCode::try_from_u32(code) {
+        Some(ErrorCode::None) => None,
+        Some(code) => Some((code, msg)),
+        None => Some((ErrorCode::Other, msg)),
+    }
+}
+
+/// Select from a list of pending requests, blocking until one completes or until `timeout` has
+/// passed.
+///
+/// Returns `Ok(None)` if the timeout passes before any request completes, in which case all of
+/// the pending requests remain valid. Otherwise this behaves like `select()`.
+pub fn select_timeout(
+    prs: &[&PendingRequestHandle],
+    timeout: Duration,
+) -> Result<Option<(usize, ResponseHandle)>, Option<usize>> {
+    let prs = prs.iter().map(|&pr| i32::from(pr)).collect::<Vec<i32>>();
+    let mut pr_out = 0;
+
+    let resp = unsafe {
+        raw::hostcall_pending_req_select_timeout(
+            prs.as_ptr(),
+            prs.len(),
+            &mut pr_out,
+            duration_ms(timeout),
+        )
+    };
+    let resp = ResponseHandle::from(resp);
+    if resp.is_not_ready() {
+        return Ok(None);
+    }
+    let index = prs.iter().position(|&pr| pr == pr_out);
+    if resp.is_error() {
+        Err(index)
+    } else {
+        index.map(|index| Some((index, resp))).ok_or(None)
     }
 }
 
@@ -501,3 +1101,141 @@ pub(crate) fn panic_hook(msg: &str) {
     let msg_bytes = msg.as_bytes();
     unsafe { raw::hostcall_panic_hook(msg_bytes.as_ptr(), msg_bytes.len()) }
 }
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::{self, guest, FakeUpstream, Reply};
+
+    fn send(url: &str) -> PendingRequestHandle {
+        RequestHandle::create("GET", url)
+            .unwrap()
+            .send_async()
+            .unwrap()
+    }
+
+    #[test]
+    fn select_returns_index_and_closes_each_handle_once() {
+        mock::set_fake_upstream(
+            FakeUpstream::new()
+                .route_any(
+                    "http://a/",
+                    Reply::status(200).latency(Duration::from_millis(20)),
+                )
+                .route_any(
+                    "http://b/",
+                    Reply::status(201).latency(Duration::from_millis(10)),
+                ),
+        );
+        let ids = guest(|| {
+            let (a, b) = (send("http://a/"), send("http://b/"));
+            let ids = vec![i32::from(&a), i32::from(&b)];
+            let (index, resp) = select(&[&a, &b]).unwrap();
+            assert_eq!((index, resp.get_response_code()), (1, 201));
+            let (index, resp) = select(&[&a]).unwrap();
+            assert_eq!((index, resp.get_response_code()), (0, 200));
+            ids
+        });
+        let mut closed = mock::closed_handles();
+        assert!(ids.iter().all(|id| closed.contains(id)));
+        let count = closed.len();
+        closed.sort();
+        closed.dedup();
+        assert_eq!(closed.len(), count, "a handle was closed twice");
+        assert_eq!(mock::open_handles(), 0);
+    }
+
+    #[test]
+    fn select_on_consumed_request_identifies_none() {
+        mock::set_fake_upstream(FakeUpstream::new().route_any("http://a/", Reply::status(200)));
+        guest(|| {
+            let a = send("http://a/");
+            assert_eq!(select(&[&a]).unwrap().0, 0);
+            assert_eq!(select(&[&a]).unwrap_err(), None);
+            assert_eq!(select(&[]).unwrap_err(), None);
+        });
+    }
+
+    /// Read all of `r`, at most `chunk` bytes at a time.
+    fn read_in_chunks<R: Read>(r: &mut R, chunk: usize) -> Vec<u8> {
+        let mut body = vec![];
+        let mut buf = vec![0; chunk];
+        loop {
+            match r.read(&mut buf).unwrap() {
+                0 => return body,
+                n => body.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn request_and_response_bodies_stream_through_handles() {
+        mock::set_upstream(|req| {
+            let mut body = b"echo: ".to_vec();
+            body.extend_from_slice(req.body());
+            Some(http::Response::new(body))
+        });
+        let body = guest(|| {
+            let mut req = RequestHandle::create("POST", "http://echo/").unwrap();
+            req.write_all(b"hello, ").unwrap();
+            req.write_all(b"world").unwrap();
+            let mut resp = req.send().unwrap();
+            read_in_chunks(&mut resp, 3)
+        });
+        assert_eq!(body, b"echo: hello, world");
+        assert_eq!(mock::sent_requests()[0].body(), b"hello, world");
+    }
+
+    #[test]
+    fn incoming_request_body_is_read_in_chunks() {
+        let resp = mock::run_streaming(
+            http::Request::new(b"0123456789".to_vec()),
+            |mut req: http::Request<RequestHandle>| {
+                http::Response::new(std::io::Cursor::new(read_in_chunks(req.body_mut(), 4)))
+            },
+        );
+        assert_eq!(resp.body(), b"0123456789");
+    }
+
+    #[test]
+    fn incoming_request_cannot_be_written() {
+        guest(|| {
+            let mut incoming = RequestHandle::INCOMING;
+            assert!(incoming.write(b"x").is_err());
+        });
+    }
+
+    #[test]
+    fn response_head_is_fixed_by_the_first_body_write() {
+        let resp = mock::run_streaming(
+            http::Request::new(vec![]),
+            |_: http::Request<RequestHandle>| {
+                let mut out = ResponseHandle::OUTGOING;
+                assert_eq!(out.set_response_code(201), HostcallStatus::Ok);
+                assert_eq!(out.set_header("x-a", &["1"]), HostcallStatus::Ok);
+                out.write_all(b"first").unwrap();
+                assert_eq!(out.set_response_code(500), HostcallStatus::Invalid);
+                assert_eq!(out.set_header("x-b", &["2"]), HostcallStatus::Invalid);
+                assert_eq!(
+                    out.set_version(http::Version::HTTP_10),
+                    HostcallStatus::Invalid
+                );
+                assert_eq!(out.set_reason("Nope"), HostcallStatus::Invalid);
+                assert_eq!(out.set_body(b"replaced"), HostcallStatus::Invalid);
+                // trailers follow the body, so they can still be set
+                assert_eq!(out.set_trailer_bytes("x-t", &[b"3"]), HostcallStatus::Ok);
+                out.write_all(b", second").unwrap();
+                http::Response::new(std::io::empty())
+            },
+        );
+        assert!(
+            mock::panic_messages().is_empty(),
+            "{:?}",
+            mock::panic_messages()
+        );
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers()["x-a"], "1");
+        assert!(!resp.headers().contains_key("x-b"));
+        assert_eq!(resp.body(), b"first, second");
+    }
+}
@@ -13,16 +13,33 @@ extern "C" {
 
     pub fn hostcall_req_send_async(req: i32) -> i32;
 
+    pub fn hostcall_req_close(req: i32);
+
     pub fn hostcall_pending_req_wait(pr: i32) -> i32;
 
+    pub fn hostcall_pending_req_wait_timeout(pr: i32, timeout_ms: u64) -> i32;
+
     pub fn hostcall_pending_req_poll(pr: i32) -> i32;
 
+    pub fn hostcall_pending_req_close(pr: i32);
+
+    pub fn hostcall_pending_req_cancel(pr: i32) -> HostcallStatus;
+
     pub fn hostcall_pending_req_select(
         prs_ptr: *const i32,
         prs_len: usize,
         pr_out: *mut i32,
     ) -> i32;
 
+    pub fn hostcall_pending_req_select_timeout(
+        prs_ptr: *const i32,
+        prs_len: usize,
+        pr_out: *mut i32,
+        timeout_ms: u64,
+    ) -> i32;
+
+    pub fn hostcall_last_error(msg_ptr_p: *mut *mut u8, msg_len_p: *mut usize) -> u32;
+
     pub fn hostcall_req_get_header(
         values_ptr_p: *mut *mut GuestSlice<u8>,
         values_len_p: *mut usize,
@@ -41,8 +58,50 @@ extern "C" {
 
     pub fn hostcall_req_get_body(body_ptr_p: *mut *mut u8, body_len_p: *mut usize, req: i32);
 
+    pub fn hostcall_req_body_read(
+        req: i32,
+        buf_ptr: *mut u8,
+        buf_len: usize,
+        nread_p: *mut usize,
+    ) -> HostcallStatus;
+
     pub fn hostcall_req_get_path(path_ptr_p: *mut *mut u8, path_len_p: *mut usize, req: i32);
 
+    pub fn hostcall_req_get_scheme(scheme_ptr_p: *mut *mut u8, scheme_len_p: *mut usize, req: i32);
+
+    pub fn hostcall_req_get_authority(
+        authority_ptr_p: *mut *mut u8,
+        authority_len_p: *mut usize,
+        req: i32,
+    );
+
+    pub fn hostcall_req_get_query(query_ptr_p: *mut *mut u8, query_len_p: *mut usize, req: i32);
+
+    pub fn hostcall_req_get_version(req: i32) -> u32;
+
+    pub fn hostcall_req_get_client_addr(
+        addr_ptr: *mut u8,
+        addr_len_p: *mut usize,
+        port_p: *mut u16,
+        req: i32,
+    ) -> bool;
+
+    pub fn hostcall_req_get_tls_protocol(
+        protocol_ptr_p: *mut *mut u8,
+        protocol_len_p: *mut usize,
+        req: i32,
+    );
+
+    pub fn hostcall_req_get_tls_cipher(
+        cipher_ptr_p: *mut *mut u8,
+        cipher_len_p: *mut usize,
+        req: i32,
+    );
+
+    pub fn hostcall_req_get_tls_sni(sni_ptr_p: *mut *mut u8, sni_len_p: *mut usize, req: i32);
+
+    pub fn hostcall_req_get_tls_alpn(alpn_ptr_p: *mut *mut u8, alpn_len_p: *mut usize, req: i32);
+
     pub fn hostcall_req_set_header(
         req: i32,
         name_ptr: *const u8,
@@ -51,8 +110,36 @@ extern "C" {
         values_slice_len: usize,
     ) -> HostcallStatus;
 
+    pub fn hostcall_req_get_trailers(
+        trailers_ptr_p: *mut *mut GuestSlice<u8>,
+        trailers_len_p: *mut usize,
+        req: i32,
+    );
+
+    pub fn hostcall_req_get_trailer(
+        values_ptr_p: *mut *mut GuestSlice<u8>,
+        values_len_p: *mut usize,
+        req: i32,
+        name_ptr: *const u8,
+        name_len: usize,
+    );
+
+    pub fn hostcall_req_set_trailer(
+        req: i32,
+        name_ptr: *const u8,
+        name_len: usize,
+        values_slice_ptr: *const GuestSlice<u8>,
+        values_slice_len: usize,
+    ) -> HostcallStatus;
+
     pub fn hostcall_req_set_body(req: i32, body_ptr: *const u8, body_len: usize) -> HostcallStatus;
 
+    pub fn hostcall_req_body_write(req: i32, buf_ptr: *const u8, buf_len: usize) -> HostcallStatus;
+
+    pub fn hostcall_req_set_version(req: i32, version: u32) -> HostcallStatus;
+
+    pub fn hostcall_req_set_timeouts(req: i32, connect_ms: u64, total_ms: u64) -> HostcallStatus;
+
     pub fn hostcall_resp_get_headers(
         headers_ptr_p: *mut *mut GuestSlice<u8>,
         headers_len_p: *mut usize,
@@ -69,8 +156,41 @@ extern "C" {
 
     pub fn hostcall_resp_get_body(body_ptr_p: *mut *mut u8, body_len_p: *mut usize, resp: i32);
 
+    pub fn hostcall_resp_body_read(
+        resp: i32,
+        buf_ptr: *mut u8,
+        buf_len: usize,
+        nread_p: *mut usize,
+    ) -> HostcallStatus;
+
     pub fn hostcall_resp_get_response_code(resp: i32) -> u32;
 
+    pub fn hostcall_resp_close(resp: i32);
+
+    pub fn hostcall_resp_get_version(resp: i32) -> u32;
+
+    pub fn hostcall_resp_get_timings(
+        dns_us_p: *mut u64,
+        connect_us_p: *mut u64,
+        tls_us_p: *mut u64,
+        first_byte_us_p: *mut u64,
+        total_us_p: *mut u64,
+        resp: i32,
+    );
+
+    pub fn hostcall_resp_get_remote_addr(
+        addr_ptr: *mut u8,
+        addr_len_p: *mut usize,
+        port_p: *mut u16,
+        resp: i32,
+    ) -> bool;
+
+    pub fn hostcall_resp_get_tls_protocol(
+        protocol_ptr_p: *mut *mut u8,
+        protocol_len_p: *mut usize,
+        resp: i32,
+    );
+
     pub fn hostcall_resp_set_header(
         resp: i32,
         name_ptr: *const u8,
@@ -79,14 +199,50 @@ extern "C" {
         values_len_p: usize,
     ) -> HostcallStatus;
 
+    pub fn hostcall_resp_get_trailers(
+        trailers_ptr_p: *mut *mut GuestSlice<u8>,
+        trailers_len_p: *mut usize,
+        resp: i32,
+    );
+
+    pub fn hostcall_resp_get_trailer(
+        values_ptr_p: *mut *mut GuestSlice<u8>,
+        values_len_p: *mut usize,
+        resp: i32,
+        name_ptr: *const u8,
+        name_len: usize,
+    );
+
+    pub fn hostcall_resp_set_trailer(
+        resp: i32,
+        name_ptr: *const u8,
+        name_len: usize,
+        values_slice_ptr: *const GuestSlice<u8>,
+        values_slice_len: usize,
+    ) -> HostcallStatus;
+
     pub fn hostcall_resp_set_body(
         resp: i32,
         body_ptr: *const u8,
         body_len: usize,
     ) -> HostcallStatus;
 
+    pub fn hostcall_resp_body_write(
+        resp: i32,
+        buf_ptr: *const u8,
+        buf_len: usize,
+    ) -> HostcallStatus;
+
     pub fn hostcall_resp_set_response_code(resp: i32, code: u16) -> HostcallStatus;
 
+    pub fn hostcall_resp_set_version(resp: i32, version: u32) -> HostcallStatus;
+
+    pub fn hostcall_resp_set_reason(
+        resp: i32,
+        reason_ptr: *const u8,
+        reason_len: usize,
+    ) -> HostcallStatus;
+
     pub fn hostcall_kvstore_insert(
         key_ptr: *const u8,
         key_len: usize,
@@ -128,6 +284,8 @@ extern "C" {
 
     pub fn hostcall_time_now(subsec_nanos_p: *mut u32) -> u64;
 
+    pub fn hostcall_time_sleep(duration_ms: u64);
+
     pub fn hostcall_dns_query_raw(
         response_ptr_p: *mut *mut u8,
         response_len_p: *mut usize,
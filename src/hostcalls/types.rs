@@ -1,5 +1,6 @@
 use std::mem;
 use std::slice;
+use std::time::Duration;
 
 #[allow(dead_code)]
 #[repr(u8)]
@@ -21,6 +22,90 @@ impl HostcallStatus {
     }
 }
 
+/// The kinds of failure reported by `hostcall_last_error`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorCode {
+    /// No failure has been reported.
+    None = 0,
+    /// The request URL or method could not be used.
+    InvalidUrl = 1,
+    /// The upstream host name could not be resolved.
+    Dns = 2,
+    /// The connection to the upstream could not be established.
+    Connect = 3,
+    /// The request did not complete in time.
+    Timeout = 4,
+    /// The TLS handshake with the upstream failed.
+    Tls = 5,
+    /// The upstream sent a malformed or unexpected message.
+    Protocol = 6,
+    /// Any other failure within the host.
+    Other = 7,
+}
+
+impl ErrorCode {
+    pub fn try_from_u32(v: u32) -> Option<ErrorCode> {
+        use self::ErrorCode::*;
+        match v {
+            0 => Some(None),
+            1 => Some(InvalidUrl),
+            2 => Some(Dns),
+            3 => Some(Connect),
+            4 => Some(Timeout),
+            5 => Some(Tls),
+            6 => Some(Protocol),
+            7 => Some(Other),
+            _ => Option::None,
+        }
+    }
+}
+
+/// The HTTP versions that the host reports and accepts.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HttpVersion {
+    Http09 = 0,
+    Http10 = 1,
+    Http11 = 2,
+    Http2 = 3,
+}
+
+impl HttpVersion {
+    pub fn try_from_u32(v: u32) -> Option<HttpVersion> {
+        use self::HttpVersion::*;
+        match v {
+            0 => Some(Http09),
+            1 => Some(Http10),
+            2 => Some(Http11),
+            3 => Some(Http2),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpVersion> for http::Version {
+    fn from(v: HttpVersion) -> http::Version {
+        match v {
+            HttpVersion::Http09 => http::Version::HTTP_09,
+            HttpVersion::Http10 => http::Version::HTTP_10,
+            HttpVersion::Http11 => http::Version::HTTP_11,
+            HttpVersion::Http2 => http::Version::HTTP_2,
+        }
+    }
+}
+
+impl From<http::Version> for HttpVersion {
+    fn from(v: http::Version) -> HttpVersion {
+        match v {
+            http::Version::HTTP_09 => HttpVersion::Http09,
+            http::Version::HTTP_10 => HttpVersion::Http10,
+            http::Version::HTTP_2 => HttpVersion::Http2,
+            _ => HttpVersion::Http11,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct GuestSlice<T> {
     ptr: *const T,
@@ -46,6 +131,10 @@ impl<T> GuestSlice<T> {
     }
 }
 
+/// A request held by the host.
+///
+/// A request created with `RequestHandle::create()` is closed in the host when its handle is
+/// dropped without being sent. `RequestHandle::INCOMING` is never closed.
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub struct RequestHandle(i32);
 
@@ -56,8 +145,11 @@ impl From<i32> for RequestHandle {
 }
 
 impl From<RequestHandle> for i32 {
+    /// Give up ownership of the handle, so that it is not closed when dropped.
     fn from(req: RequestHandle) -> i32 {
-        req.0
+        let handle = req.0;
+        mem::forget(req);
+        handle
     }
 }
 
@@ -85,6 +177,10 @@ impl RequestHandle {
     }
 }
 
+/// A response held by the host.
+///
+/// A response returned by sending a request is closed in the host when its handle is dropped,
+/// freeing its body if it has not been read. `ResponseHandle::OUTGOING` is never closed.
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub struct ResponseHandle(i32);
 
@@ -95,8 +191,11 @@ impl From<i32> for ResponseHandle {
 }
 
 impl From<ResponseHandle> for i32 {
+    /// Give up ownership of the handle, so that it is not closed when dropped.
     fn from(resp: ResponseHandle) -> i32 {
-        resp.0
+        let handle = resp.0;
+        mem::forget(resp);
+        handle
     }
 }
 
@@ -131,6 +230,11 @@ impl ResponseHandle {
     }
 }
 
+/// A request that the host is sending asynchronously.
+///
+/// A pending request is closed in the host when its handle is dropped, including after it has
+/// completed in `select()`, so that the host no longer holds on to it or its response. The
+/// exchange with the upstream is only aborted if the request is cancelled with `cancel()`.
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub struct PendingRequestHandle(i32);
 
@@ -141,8 +245,11 @@ impl From<i32> for PendingRequestHandle {
 }
 
 impl From<PendingRequestHandle> for i32 {
+    /// Give up ownership of the handle, so that it is not closed when dropped.
     fn from(resp: PendingRequestHandle) -> i32 {
-        resp.0
+        let handle = resp.0;
+        mem::forget(resp);
+        handle
     }
 }
 
@@ -173,3 +280,16 @@ pub enum PollResult {
     Response(ResponseHandle),
     Error,
 }
+
+/// How long each phase of the request that produced a response took, as reported by
+/// `hostcall_resp_get_timings`.
+///
+/// A phase is `None` if it did not happen or the host does not know how long it took.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ResponseTimings {
+    pub dns: Option<Duration>,
+    pub connect: Option<Duration>,
+    pub tls: Option<Duration>,
+    pub first_byte: Option<Duration>,
+    pub total: Option<Duration>,
+}
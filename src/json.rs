@@ -0,0 +1,250 @@
+//! JSON request and response bodies, using `serde`.
+//!
+//! These helpers are only available with the `json` feature.
+
+use failure::Fail;
+use http::{header, request, response, HeaderMap, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{problem_response, ErrorResponse};
+
+#[derive(Debug, Fail)]
+pub enum JsonError {
+    /// The body was not declared to be JSON by its `Content-Type` header, whose value, if any, is
+    /// given.
+    #[fail(display = "Unsupported content type: expected JSON, got {:?}", _0)]
+    ContentType(String),
+    /// The body could not be parsed as JSON, or did not match the type it was decoded into.
+    #[fail(display = "Invalid JSON body: {}", _0)]
+    Deserialize(#[cause] serde_json::Error),
+    /// The value could not be serialized as JSON.
+    #[fail(display = "JSON serialization error: {}", _0)]
+    Serialize(#[cause] serde_json::Error),
+    /// The request or response being built was invalid.
+    #[fail(display = "Http error: {}", _0)]
+    Http(#[cause] http::Error),
+}
+
+/// Errors decoding a body are reported as `415 Unsupported Media Type` if it was not declared to
+/// be JSON, `400 Bad Request` if it could not be decoded, and `500 Internal Server Error`
+/// otherwise.
+///
+/// This treats the body as one sent by the client, as it is when decoding the incoming request.
+/// An error decoding an upstream response should be mapped to a more fitting error first.
+impl ErrorResponse for JsonError {
+    fn error_response(&self) -> Response<Vec<u8>> {
+        let status = match *self {
+            JsonError::ContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonError::Deserialize(_) => StatusCode::BAD_REQUEST,
+            JsonError::Serialize(_) | JsonError::Http(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        problem_response(status, self)
+    }
+}
+
+/// A value to send as a JSON response.
+///
+/// A `guest_app_result` handler can return this in place of a `Response`, and it is sent with a
+/// `200 OK` status and an `application/json` content type:
+///
+/// ```text
+/// pub fn user_entrypoint(req: &Request<Vec<u8>>) -> Result<Json<Greeting>, JsonError> {
+///     let name: Name = req.json()?;
+///     Ok(Json(Greeting { message: format!("Hello, {}!", name.first) }))
+/// }
+/// ```
+///
+/// If the value cannot be serialized, a `500 Internal Server Error` is sent instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> From<Json<T>> for Response<Vec<u8>> {
+    fn from(json: Json<T>) -> Response<Vec<u8>> {
+        Response::builder()
+            .json(&json.0)
+            .unwrap_or_else(|e| e.error_response())
+    }
+}
+
+/// Extension trait for building requests and responses with JSON bodies.
+pub trait JsonBuilderExt {
+    type Output;
+
+    /// Serialize `value` as the JSON body, and set the `Content-Type` header to
+    /// `application/json`.
+    ///
+    /// ```text
+    /// let resp = Request::post("https://api.example.com/users")
+    ///     .json(&NewUser { name: "ferris" })?
+    ///     .send()?;
+    /// ```
+    fn json<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<Self::Output, JsonError>;
+}
+
+impl JsonBuilderExt for request::Builder {
+    type Output = Request<Vec<u8>>;
+
+    fn json<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<Request<Vec<u8>>, JsonError> {
+        let body = serde_json::to_vec(value).map_err(JsonError::Serialize)?;
+        self.header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .map_err(JsonError::Http)
+    }
+}
+
+impl JsonBuilderExt for response::Builder {
+    type Output = Response<Vec<u8>>;
+
+    fn json<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<Response<Vec<u8>>, JsonError> {
+        let body = serde_json::to_vec(value).map_err(JsonError::Serialize)?;
+        self.header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .map_err(JsonError::Http)
+    }
+}
+
+/// Extension trait for decoding the JSON bodies of requests and responses.
+pub trait JsonBodyExt {
+    /// Decode the JSON body.
+    ///
+    /// Returns `JsonError::ContentType` unless the `Content-Type` header is `application/json`,
+    /// or another type with a `+json` suffix.
+    fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError>;
+}
+
+impl JsonBodyExt for Request<Vec<u8>> {
+    fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        decode(self.headers(), self.body())
+    }
+}
+
+impl JsonBodyExt for Response<Vec<u8>> {
+    fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        decode(self.headers(), self.body())
+    }
+}
+
+fn decode<T: DeserializeOwned>(headers: &HeaderMap, body: &[u8]) -> Result<T, JsonError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        .unwrap_or_default();
+    if !is_json(&content_type) {
+        return Err(JsonError::ContentType(content_type));
+    }
+    serde_json::from_slice(body).map_err(JsonError::Deserialize)
+}
+
+/// Whether a media type, possibly with parameters, is JSON.
+fn is_json(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    essence == "application/json" || (essence.contains('/') && essence.ends_with("+json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::post;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn json_content_types_are_accepted() {
+        for content_type in &[
+            "application/json",
+            "application/json; charset=utf-8",
+            "Application/JSON",
+            "application/problem+json",
+            "application/vnd.api+json; ext=bulk",
+        ] {
+            let value: Vec<u32> = post("/", Some(content_type), "[1, 2, 3]").json().unwrap();
+            assert_eq!(value, [1, 2, 3], "{}", content_type);
+        }
+    }
+
+    #[test]
+    fn other_content_types_are_unsupported() {
+        for content_type in &[None, Some("text/plain"), Some("json"), Some("+json")] {
+            match post("/", *content_type, "[1]").json::<Vec<u32>>() {
+                Err(e @ JsonError::ContentType(_)) => {
+                    assert_eq!(e.error_response().status(), 415, "{:?}", content_type);
+                }
+                other => panic!("{:?}: {:?}", content_type, other),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_bodies_are_bad_requests() {
+        for body in &["", "[1,", "{\"a\": 1}", "[\"one\"]"] {
+            match post("/", Some("application/json"), body).json::<Vec<u32>>() {
+                Err(e @ JsonError::Deserialize(_)) => {
+                    let resp = e.error_response();
+                    assert_eq!(resp.status(), 400, "{:?}", body);
+                    assert_eq!(
+                        resp.headers()[header::CONTENT_TYPE],
+                        "application/problem+json"
+                    );
+                }
+                other => panic!("{:?}: {:?}", body, other),
+            }
+        }
+    }
+
+    #[test]
+    fn json_values_become_responses() {
+        let mut value = BTreeMap::new();
+        value.insert("message", "Hello, \"ferris\"!");
+        let resp = Response::from(Json(value));
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(resp.body(), br#"{"message":"Hello, \"ferris\"!"}"#);
+
+        let back: HashMap<String, String> = resp.json().unwrap();
+        assert_eq!(back["message"], "Hello, \"ferris\"!");
+    }
+
+    #[test]
+    fn unserializable_values_become_server_errors() {
+        // JSON object keys must be strings
+        let mut value = HashMap::new();
+        value.insert((1, 2), 3);
+        let resp = Response::from(Json(value));
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+    }
+
+    #[test]
+    fn builders_set_the_body_and_content_type() {
+        let req = Request::post("https://api.example.com/users")
+            .header("x-request-id", "7")
+            .json(&("ferris", 7))
+            .unwrap();
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.headers()["x-request-id"], "7");
+        assert_eq!(req.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(req.body(), br#"["ferris",7]"#);
+        assert_eq!(
+            req.json::<(String, u32)>().unwrap(),
+            ("ferris".to_owned(), 7)
+        );
+
+        let resp = Response::builder().status(201).json(&[true]).unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(resp.body(), b"[true]");
+
+        match Response::builder().status(1000).json(&[true]) {
+            Err(e @ JsonError::Http(_)) => assert_eq!(e.error_response().status(), 500),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -1,28 +1,82 @@
 //! API for guest applications in the `isolation-demo` environment.
+//!
+//! # Panics
+//!
+//! The scaffolding macros send a fallback response, given as their
+//! `on_panic` argument, when the handler panics, but only if the guest
+//! unwinds on panic. The standard library that rustup ships for
+//! `wasm32-unknown-unknown` is built with `panic = "abort"`, so setting
+//! `panic = "unwind"` in a profile has no effect on that target, and a
+//! panic traps the instance before any response is sent.
+//!
+//! Unwinding on the deploy target needs a nightly toolchain that
+//! rebuilds the standard library with unwinding support, using
+//! WebAssembly exceptions:
+//!
+//! ```text
+//! RUSTFLAGS="-C panic=unwind -C target-feature=+exception-handling" \
+//!     cargo +nightly build -Z build-std=std,panic_unwind --target wasm32-unknown-unknown
+//! ```
+//!
+//! and a host whose WebAssembly engine supports the exception handling
+//! proposal. Native builds, such as tests run against the `mock` host,
+//! unwind by default.
 
 extern crate coarsetime;
 extern crate failure;
 extern crate http;
 extern crate rand_core;
+#[cfg(any(feature = "json", feature = "form"))]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
 
 mod client;
+mod client_info;
 mod dns;
+mod error;
+pub mod executor;
+mod extract;
 mod guest_allocator;
 pub mod hostcalls;
+#[cfg(feature = "json")]
+mod json;
 pub mod kvstore;
+#[cfg(feature = "mock")]
+pub mod mock;
 mod panic;
 pub mod rand;
+pub mod routing;
 pub mod time;
+mod trailers;
 #[macro_use]
 mod scaffolding;
+#[cfg(test)]
+mod test_util;
 
-pub use crate::client::{select, PendingRequest, PollResult, RequestExt, SendError};
+pub use crate::client::{
+    join_all, select, select_hedged, select_timeout, Attempts, FanOut, PendingRequest, PollResult,
+    RedirectChain, RequestExt, ResponseMetadata, RetryError, RetryPolicy, RetryingRequest,
+    SendError, Timeouts, Timings,
+};
+pub use crate::client_info::{ClientInfo, TlsInfo};
 pub use crate::dns::DNS;
+pub use crate::error::{problem_response, ErrorResponse};
+pub use crate::extract::{ExtractError, ExtractExt, FormData, Multipart, Part};
+pub use crate::hostcalls::{RequestHandle, ResponseHandle};
+#[cfg(feature = "json")]
+pub use crate::json::{Json, JsonBodyExt, JsonBuilderExt, JsonError};
 pub use crate::kvstore::KVStore;
+pub use crate::routing::{Handler, Params, Router};
+pub use crate::scaffolding::{Middleware, ReasonPhrase, Stack};
 pub use crate::time::Time;
-pub use http::{header, Error, HeaderMap, Method, Request, Response, StatusCode, Uri};
+pub use crate::trailers::Trailers;
+pub use http::{header, Error, HeaderMap, Method, Request, Response, StatusCode, Uri, Version};
 
 // export these for the scaffolding macro
 pub use crate::guest_allocator::init_mm_default;
-pub use crate::panic::panic_set_once;
-pub use crate::scaffolding::{raw_entrypoint, raw_entrypoint_kvs};
+pub use crate::panic::{panic_set_once, set_panic_response};
+pub use crate::scaffolding::{
+    raw_entrypoint, raw_entrypoint_async, raw_entrypoint_kvs, raw_entrypoint_kvs_with,
+    raw_entrypoint_result, raw_entrypoint_streaming, raw_entrypoint_with,
+};
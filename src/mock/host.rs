@@ -0,0 +1,189 @@
+//! State backing the mock hostcalls.
+//!
+//! Each thread gets its own host, so tests running in parallel under
+//! `cargo test` do not observe each other's requests or stores.
+
+use http::{Request, Response};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::os::raw::c_void;
+use std::time::Duration;
+use std::{mem, ptr};
+
+use crate::hostcalls::types::{ErrorCode, GuestSlice};
+use crate::mock::upstream::{Reply, Upstream};
+
+/// The response to an outbound request, or the error it fails with.
+pub(crate) type Outcome = Result<Response<Vec<u8>>, (ErrorCode, String)>;
+
+/// An outbound request that has been sent asynchronously.
+pub(crate) struct Pending {
+    /// The time on the mock clock at which the request completes.
+    pub(crate) ready_at: Duration,
+    pub(crate) outcome: Outcome,
+}
+
+#[derive(Default)]
+pub(crate) struct Host {
+    malloc_impl: Option<extern "C" fn(size: usize) -> *mut c_void>,
+    next_handle: i32,
+    pub(crate) requests: HashMap<i32, Request<Vec<u8>>>,
+    pub(crate) responses: HashMap<i32, Response<Vec<u8>>>,
+    pub(crate) pending: HashMap<i32, Pending>,
+    /// Every handle the guest has closed, in order, so that tests can
+    /// check that none is closed twice.
+    pub(crate) closed: Vec<i32>,
+    /// Every pending request the guest has cancelled, in order.
+    pub(crate) cancelled: Vec<i32>,
+    /// Whether the guest has started writing the outgoing response
+    /// body, after which its status and headers have been sent.
+    pub(crate) streaming: bool,
+    pub(crate) upstream: Option<Box<dyn Upstream>>,
+    pub(crate) sent: Vec<Request<Vec<u8>>>,
+    pub(crate) kvstore: HashMap<String, Vec<u8>>,
+    pub(crate) dns_ips: HashMap<String, Vec<IpAddr>>,
+    pub(crate) dns_raw: HashMap<Vec<u8>, Vec<u8>>,
+    pub(crate) last_error: Option<(ErrorCode, String)>,
+    pub(crate) rng_state: u64,
+    pub(crate) now: Duration,
+    pub(crate) debug_log: Vec<String>,
+    pub(crate) panic_log: Vec<String>,
+}
+
+thread_local! {
+    static HOST: RefCell<Host> = RefCell::new(Host::default());
+}
+
+/// Run a closure with exclusive access to this thread's host.
+pub(crate) fn with_host<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Host) -> R,
+{
+    HOST.with(|host| f(&mut host.borrow_mut()))
+}
+
+/// Run the upstream for an outbound request, and record the request
+/// as sent.
+///
+/// Without a configured upstream, every request fails. The upstream is
+/// called without the host borrowed, so it may use the `mock` API.
+pub(crate) fn dispatch(req: Request<Vec<u8>>) -> Reply {
+    let upstream = with_host(|host| host.upstream.take());
+    let reply = match upstream {
+        Some(ref upstream) => upstream.reply(&req),
+        None => Reply::error(ErrorCode::Connect, "no upstream configured"),
+    };
+    with_host(|host| {
+        if host.upstream.is_none() {
+            host.upstream = upstream;
+        }
+        host.sent.push(req);
+    });
+    reply
+}
+
+impl Host {
+    /// Prepare the host for a fresh execution of the guest, discarding
+    /// the handles and logs of any previous execution.
+    pub(crate) fn start(&mut self, req: Request<Vec<u8>>) {
+        self.requests.clear();
+        self.responses.clear();
+        self.pending.clear();
+        self.closed.clear();
+        self.cancelled.clear();
+        self.sent.clear();
+        self.streaming = false;
+        self.last_error = None;
+        self.debug_log.clear();
+        self.panic_log.clear();
+        self.next_handle = 1;
+        // like a real host, answer in the client's version unless the
+        // guest chooses another
+        let mut resp = Response::new(vec![]);
+        *resp.version_mut() = req.version();
+        self.requests.insert(0, req);
+        self.responses.insert(0, resp);
+    }
+
+    pub(crate) fn set_malloc(&mut self, malloc_impl: extern "C" fn(size: usize) -> *mut c_void) {
+        self.malloc_impl = Some(malloc_impl);
+    }
+
+    /// Record a failure for `hostcall_last_error`, returning the error
+    /// handle for the failed hostcall.
+    pub(crate) fn fail(&mut self, code: ErrorCode, msg: &str) -> i32 {
+        self.last_error = Some((code, msg.to_owned()));
+        -1
+    }
+
+    pub(crate) fn next_handle(&mut self) -> i32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+
+    /// Allocate memory owned by the guest, as the real host does with
+    /// the allocator passed to `hostcall_init_mm`.
+    fn alloc(&self, size: usize) -> *mut u8 {
+        let malloc_impl = self
+            .malloc_impl
+            .expect("`hostcall_init_mm` must be called before any hostcall that returns memory");
+        malloc_impl(size) as *mut u8
+    }
+
+    /// Copy bytes into guest memory.
+    ///
+    /// Empty inputs produce a null pointer, which the guest never
+    /// reads or frees when the accompanying length is zero.
+    pub(crate) fn alloc_bytes(&self, bytes: &[u8]) -> *mut u8 {
+        if bytes.is_empty() {
+            ptr::null_mut()
+        } else {
+            self.alloc_copy(bytes)
+        }
+    }
+
+    /// Copy bytes into guest memory, making a real allocation even for
+    /// empty inputs, for results that the guest frees unconditionally.
+    pub(crate) fn alloc_copy(&self, bytes: &[u8]) -> *mut u8 {
+        let dst = self.alloc(bytes.len().max(1));
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), dst, bytes.len()) };
+        dst
+    }
+
+    /// Copy a list of byte strings into guest memory as an array of
+    /// `GuestSlice`s, each of which is itself guest-owned.
+    pub(crate) fn alloc_slices<I>(&self, items: I) -> (*mut GuestSlice<u8>, usize)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let slices = items
+            .into_iter()
+            .map(|item| {
+                let item = item.as_ref();
+                GuestSlice::new(self.alloc_copy(item), item.len())
+            })
+            .collect::<Vec<GuestSlice<u8>>>();
+        if slices.is_empty() {
+            return (ptr::null_mut(), 0);
+        }
+        let dst =
+            self.alloc(slices.len() * mem::size_of::<GuestSlice<u8>>()) as *mut GuestSlice<u8>;
+        let len = slices.len();
+        for (i, slice) in slices.into_iter().enumerate() {
+            unsafe { ptr::write_unaligned(dst.add(i), slice) };
+        }
+        (dst, len)
+    }
+
+    /// Produce the next value of a SplitMix64 sequence.
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,1258 @@
+//! Native definitions of the symbols imported in `hostcalls::raw`.
+//!
+//! Linking these into the guest resolves the imports that would
+//! otherwise only be satisfied by the Terrarium runtime.
+
+use http::header::{HeaderName, HeaderValue};
+use http::{Method, Request, StatusCode, Uri};
+use std::collections::hash_map::Entry;
+use std::net::{IpAddr, SocketAddr};
+use std::os::raw::c_void;
+use std::time::Duration;
+use std::{ptr, slice};
+
+use crate::client::{ResponseMetadata, Timeouts};
+use crate::client_info::{ClientInfo, TlsInfo};
+use crate::hostcalls::types::{ErrorCode, GuestSlice, HostcallStatus, HttpVersion, ResponseHandle};
+use crate::mock::host::{dispatch, with_host, Outcome, Pending};
+use crate::scaffolding::ReasonPhrase;
+use crate::trailers::Trailers;
+
+const ERROR: i32 = -1;
+
+/// View a guest buffer passed into a hostcall.
+unsafe fn guest_bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// View a guest buffer as a string, as the host would after decoding.
+unsafe fn guest_str(ptr: *const u8, len: usize) -> String {
+    String::from_utf8_lossy(guest_bytes(ptr, len)).into_owned()
+}
+
+/// Hand bytes back to the guest through a pair of out-pointers.
+unsafe fn return_bytes(ptr_p: *mut *mut u8, len_p: *mut usize, bytes: &[u8]) {
+    let ptr = with_host(|host| host.alloc_bytes(bytes));
+    *ptr_p = ptr;
+    *len_p = bytes.len();
+}
+
+/// Hand a list of byte strings back to the guest through a pair of
+/// out-pointers.
+unsafe fn return_slices<I>(ptr_p: *mut *mut GuestSlice<u8>, len_p: *mut usize, items: I)
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let (ptr, len) = with_host(|host| host.alloc_slices(items));
+    *ptr_p = ptr;
+    *len_p = len;
+}
+
+/// Write a socket address into the guest's 16-byte address buffer and
+/// port out-pointer.
+unsafe fn return_addr(
+    addr: SocketAddr,
+    addr_ptr: *mut u8,
+    addr_len_p: *mut usize,
+    port_p: *mut u16,
+) {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    ptr::copy_nonoverlapping(ip.as_ptr(), addr_ptr, ip.len());
+    *addr_len_p = ip.len();
+    *port_p = addr.port();
+}
+
+fn set_header(
+    headers: &mut http::HeaderMap,
+    name: &[u8],
+    values: &[GuestSlice<u8>],
+) -> HostcallStatus {
+    let name = match HeaderName::from_bytes(name) {
+        Ok(name) => name,
+        Err(_) => return HostcallStatus::Invalid,
+    };
+    let mut parsed = vec![];
+    for value in values {
+        match HeaderValue::from_bytes(unsafe { value.to_slice() }) {
+            Ok(value) => parsed.push(value),
+            Err(_) => return HostcallStatus::Invalid,
+        }
+    }
+    headers.remove(&name);
+    for value in parsed {
+        headers.append(&name, value);
+    }
+    HostcallStatus::Ok
+}
+
+/// Move the front of a body into a guest buffer, as the host does
+/// when the guest reads a body in chunks.
+unsafe fn read_body(
+    body: &mut Vec<u8>,
+    buf_ptr: *mut u8,
+    buf_len: usize,
+    nread_p: *mut usize,
+) -> HostcallStatus {
+    let n = buf_len.min(body.len());
+    if n > 0 {
+        ptr::copy_nonoverlapping(body.as_ptr(), buf_ptr, n);
+    }
+    body.drain(..n);
+    *nread_p = n;
+    HostcallStatus::Ok
+}
+
+fn header_names(headers: &http::HeaderMap) -> Vec<Vec<u8>> {
+    headers
+        .keys()
+        .map(|name| name.as_str().as_bytes().to_vec())
+        .collect()
+}
+
+fn header_values(headers: &http::HeaderMap, name: &[u8]) -> Vec<Vec<u8>> {
+    match HeaderName::from_bytes(name) {
+        Ok(name) => headers
+            .get_all(&name)
+            .iter()
+            .map(|v| v.as_bytes().to_vec())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// The trailers of a request or response, kept in its extensions.
+fn trailers(extensions: &http::Extensions) -> http::HeaderMap {
+    extensions
+        .get::<Trailers>()
+        .map(|trailers| trailers.0.clone())
+        .unwrap_or_default()
+}
+
+/// Set a trailer in a request or response's extensions, creating its
+/// `Trailers` if it has none yet.
+fn set_trailer(
+    extensions: &mut http::Extensions,
+    name: &[u8],
+    values: &[GuestSlice<u8>],
+) -> HostcallStatus {
+    let mut map = trailers(extensions);
+    let status = set_header(&mut map, name, values);
+    if status == HostcallStatus::Ok {
+        extensions.insert(Trailers(map));
+    }
+    status
+}
+
+/// Take an outbound request out of the host, ready to be sent.
+fn take_request(req: i32) -> Option<Request<Vec<u8>>> {
+    if req == 0 {
+        return None;
+    }
+    with_host(|host| host.requests.remove(&req))
+}
+
+/// Record a failure for `hostcall_last_error`, returning the error
+/// handle.
+fn fail(code: ErrorCode, msg: &str) -> i32 {
+    with_host(|host| host.fail(code, msg))
+}
+
+/// Turn the outcome of a request into a response handle, or record its
+/// failure.
+fn respond(outcome: Outcome) -> i32 {
+    with_host(|host| match outcome {
+        Ok(resp) => {
+            let handle = host.next_handle();
+            host.responses.insert(handle, resp);
+            handle
+        }
+        Err((code, msg)) => host.fail(code, &msg),
+    })
+}
+
+/// Resolve a pending request, advancing the clock to the time it
+/// completes, and return a response handle.
+fn complete(pr: i32) -> i32 {
+    let pending = with_host(|host| {
+        let pending = host.pending.remove(&pr)?;
+        host.now = host.now.max(pending.ready_at);
+        Some(pending)
+    });
+    match pending {
+        Some(pending) => respond(pending.outcome),
+        None => fail(ErrorCode::Other, "invalid pending request handle"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_create(
+    method_ptr: *const u8,
+    method_len: usize,
+    url_ptr: *const u8,
+    url_len: usize,
+) -> i32 {
+    let method = match Method::from_bytes(guest_bytes(method_ptr, method_len)) {
+        Ok(method) => method,
+        Err(_) => return fail(ErrorCode::InvalidUrl, "invalid method"),
+    };
+    let uri = match guest_str(url_ptr, url_len).parse::<Uri>() {
+        Ok(ref uri) if uri.scheme_part().is_none() || uri.authority_part().is_none() => {
+            return fail(ErrorCode::InvalidUrl, "relative URL without a base")
+        }
+        Ok(uri) => uri,
+        Err(e) => return fail(ErrorCode::InvalidUrl, &e.to_string()),
+    };
+    let mut req = Request::new(vec![]);
+    *req.method_mut() = method;
+    *req.uri_mut() = uri;
+    with_host(|host| {
+        let handle = host.next_handle();
+        host.requests.insert(handle, req);
+        handle
+    })
+}
+
+/// Send an outbound request to the upstream, returning how long it
+/// takes on the mock clock and its outcome.
+fn send(req: i32) -> Result<(Duration, Outcome), i32> {
+    let req = match take_request(req) {
+        Some(req) => req,
+        None => return Err(fail(ErrorCode::Other, "invalid request handle")),
+    };
+    let total = req.extensions().get::<Timeouts>().and_then(|t| t.total);
+    let version = req.version();
+    let reply = dispatch(req);
+    match total {
+        Some(total) if reply.get_latency() > total => Ok((
+            total,
+            Err((ErrorCode::Timeout, "request timed out".to_owned())),
+        )),
+        _ => Ok((reply.get_latency(), reply.to_response(version))),
+    }
+}
+
+/// Find the pending request that completes first, preferring earlier
+/// ones in the list on a tie. Returns `None` if the list is empty or
+/// any handle in it is not pending, as the host rejects such a list.
+unsafe fn first_ready(prs_ptr: *const i32, prs_len: usize) -> Option<(i32, Duration)> {
+    let prs = if prs_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(prs_ptr, prs_len)
+    };
+    with_host(|host| {
+        prs.iter()
+            .map(|pr| host.pending.get(pr).map(|pending| (*pr, pending.ready_at)))
+            .collect::<Option<Vec<(i32, Duration)>>>()?
+            .into_iter()
+            .enumerate()
+            .min_by_key(|&(i, (_, ready_at))| (ready_at, i))
+            .map(|(_, first)| first)
+    })
+}
+
+/// Whether something ready at `ready_at` completes within `timeout_ms`
+/// of now; if not, the clock advances by the timeout.
+fn ready_within(ready_at: Duration, timeout_ms: u64) -> bool {
+    with_host(|host| {
+        let deadline = host.now + Duration::from_millis(timeout_ms);
+        if ready_at <= deadline {
+            true
+        } else {
+            host.now = deadline;
+            false
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_send(req: i32) -> i32 {
+    match send(req) {
+        Ok((latency, outcome)) => {
+            with_host(|host| host.now += latency);
+            respond(outcome)
+        }
+        Err(error) => error,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_send_async(req: i32) -> i32 {
+    match send(req) {
+        Ok((latency, outcome)) => with_host(|host| {
+            let handle = host.next_handle();
+            let pending = Pending {
+                ready_at: host.now + latency,
+                outcome,
+            };
+            host.pending.insert(handle, pending);
+            handle
+        }),
+        Err(error) => error,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_close(req: i32) {
+    if req != 0 {
+        with_host(|host| {
+            host.requests.remove(&req);
+            host.closed.push(req);
+        });
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_pending_req_wait(pr: i32) -> i32 {
+    complete(pr)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_pending_req_wait_timeout(pr: i32, timeout_ms: u64) -> i32 {
+    match with_host(|host| host.pending.get(&pr).map(|pending| pending.ready_at)) {
+        Some(ready_at) if !ready_within(ready_at, timeout_ms) => {
+            i32::from(ResponseHandle::NOT_READY)
+        }
+        _ => complete(pr),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_pending_req_poll(pr: i32) -> i32 {
+    hostcall_pending_req_wait_timeout(pr, 0)
+}
+
+/// Abandon a pending request. The upstream has already been asked, so
+/// it still appears in `sent_requests`.
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_pending_req_close(pr: i32) {
+    with_host(|host| {
+        host.pending.remove(&pr);
+        host.closed.push(pr);
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_pending_req_cancel(pr: i32) -> HostcallStatus {
+    with_host(|host| {
+        host.cancelled.push(pr);
+        match host.pending.remove(&pr) {
+            Some(ref pending) if pending.ready_at > host.now => HostcallStatus::Ok,
+            _ => HostcallStatus::Invalid,
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_pending_req_select(
+    prs_ptr: *const i32,
+    prs_len: usize,
+    pr_out: *mut i32,
+) -> i32 {
+    match first_ready(prs_ptr, prs_len) {
+        Some((pr, _)) => {
+            *pr_out = pr;
+            complete(pr)
+        }
+        None => {
+            *pr_out = ERROR;
+            fail(ErrorCode::Other, "no valid pending requests to select from")
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_pending_req_select_timeout(
+    prs_ptr: *const i32,
+    prs_len: usize,
+    pr_out: *mut i32,
+    timeout_ms: u64,
+) -> i32 {
+    match first_ready(prs_ptr, prs_len) {
+        Some((_, ready_at)) if !ready_within(ready_at, timeout_ms) => {
+            *pr_out = ERROR;
+            i32::from(ResponseHandle::NOT_READY)
+        }
+        _ => hostcall_pending_req_select(prs_ptr, prs_len, pr_out),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_last_error(
+    msg_ptr_p: *mut *mut u8,
+    msg_len_p: *mut usize,
+) -> u32 {
+    match with_host(|host| host.last_error.clone()) {
+        Some((code, msg)) => {
+            return_bytes(msg_ptr_p, msg_len_p, msg.as_bytes());
+            code as u32
+        }
+        None => {
+            *msg_ptr_p = ptr::null_mut();
+            *msg_len_p = 0;
+            ErrorCode::None as u32
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_header(
+    values_ptr_p: *mut *mut GuestSlice<u8>,
+    values_len_p: *mut usize,
+    req: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+) {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| header_values(req.headers(), name))
+            .unwrap_or_default()
+    });
+    return_slices(values_ptr_p, values_len_p, values);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_headers(
+    headers_ptr_p: *mut *mut GuestSlice<u8>,
+    headers_len_p: *mut usize,
+    req: i32,
+) {
+    let names = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| header_names(req.headers()))
+            .unwrap_or_default()
+    });
+    return_slices(headers_ptr_p, headers_len_p, names);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_trailers(
+    trailers_ptr_p: *mut *mut GuestSlice<u8>,
+    trailers_len_p: *mut usize,
+    req: i32,
+) {
+    let names = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| header_names(&trailers(req.extensions())))
+            .unwrap_or_default()
+    });
+    return_slices(trailers_ptr_p, trailers_len_p, names);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_trailer(
+    values_ptr_p: *mut *mut GuestSlice<u8>,
+    values_len_p: *mut usize,
+    req: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+) {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| header_values(&trailers(req.extensions()), name))
+            .unwrap_or_default()
+    });
+    return_slices(values_ptr_p, values_len_p, values);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_method(
+    method_ptr_p: *mut *mut u8,
+    method_len_p: *mut usize,
+    req: i32,
+) {
+    let method = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| req.method().as_str().to_owned())
+            .unwrap_or_default()
+    });
+    return_bytes(method_ptr_p, method_len_p, method.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_body(
+    body_ptr_p: *mut *mut u8,
+    body_len_p: *mut usize,
+    req: i32,
+) {
+    let body = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| req.body().clone())
+            .unwrap_or_default()
+    });
+    return_bytes(body_ptr_p, body_len_p, &body);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_body_read(
+    req: i32,
+    buf_ptr: *mut u8,
+    buf_len: usize,
+    nread_p: *mut usize,
+) -> HostcallStatus {
+    with_host(|host| match host.requests.get_mut(&req) {
+        Some(r) => read_body(r.body_mut(), buf_ptr, buf_len, nread_p),
+        None => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_path(
+    path_ptr_p: *mut *mut u8,
+    path_len_p: *mut usize,
+    req: i32,
+) {
+    let path = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| req.uri().path().to_owned())
+            .unwrap_or_default()
+    });
+    return_bytes(path_ptr_p, path_len_p, path.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_scheme(
+    scheme_ptr_p: *mut *mut u8,
+    scheme_len_p: *mut usize,
+    req: i32,
+) {
+    let scheme = with_host(|host| {
+        host.requests
+            .get(&req)
+            .map(|req| req.uri().scheme_str().unwrap_or("http").to_owned())
+            .unwrap_or_default()
+    });
+    return_bytes(scheme_ptr_p, scheme_len_p, scheme.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_authority(
+    authority_ptr_p: *mut *mut u8,
+    authority_len_p: *mut usize,
+    req: i32,
+) {
+    let authority = with_host(|host| {
+        host.requests
+            .get(&req)
+            .and_then(|req| match req.uri().authority_part() {
+                Some(authority) => Some(authority.as_str().as_bytes().to_vec()),
+                None => req
+                    .headers()
+                    .get(http::header::HOST)
+                    .map(|host| host.as_bytes().to_vec()),
+            })
+            .unwrap_or_default()
+    });
+    return_bytes(authority_ptr_p, authority_len_p, &authority);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_query(
+    query_ptr_p: *mut *mut u8,
+    query_len_p: *mut usize,
+    req: i32,
+) {
+    let query = with_host(|host| {
+        host.requests
+            .get(&req)
+            .and_then(|req| req.uri().query().map(str::to_owned))
+            .unwrap_or_default()
+    });
+    return_bytes(query_ptr_p, query_len_p, query.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_version(req: i32) -> u32 {
+    with_host(|host| {
+        let version = host
+            .requests
+            .get(&req)
+            .map_or(http::Version::HTTP_11, |req| req.version());
+        HttpVersion::from(version) as u32
+    })
+}
+
+/// Look up the `ClientInfo` in the extensions of a request, as set by
+/// the test for the incoming request.
+fn client_info(req: i32) -> Option<ClientInfo> {
+    with_host(|host| {
+        host.requests
+            .get(&req)
+            .and_then(|req| req.extensions().get::<ClientInfo>().cloned())
+    })
+}
+
+fn tls_field<F>(req: i32, field: F) -> String
+where
+    F: FnOnce(TlsInfo) -> Option<String>,
+{
+    client_info(req)
+        .and_then(|info| info.tls)
+        .and_then(field)
+        .unwrap_or_default()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_client_addr(
+    addr_ptr: *mut u8,
+    addr_len_p: *mut usize,
+    port_p: *mut u16,
+    req: i32,
+) -> bool {
+    match client_info(req).and_then(|info| info.addr) {
+        Some(addr) => {
+            return_addr(addr, addr_ptr, addr_len_p, port_p);
+            true
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_tls_protocol(
+    protocol_ptr_p: *mut *mut u8,
+    protocol_len_p: *mut usize,
+    req: i32,
+) {
+    let protocol = tls_field(req, |tls| Some(tls.protocol));
+    return_bytes(protocol_ptr_p, protocol_len_p, protocol.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_tls_cipher(
+    cipher_ptr_p: *mut *mut u8,
+    cipher_len_p: *mut usize,
+    req: i32,
+) {
+    let cipher = tls_field(req, |tls| Some(tls.cipher));
+    return_bytes(cipher_ptr_p, cipher_len_p, cipher.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_tls_sni(
+    sni_ptr_p: *mut *mut u8,
+    sni_len_p: *mut usize,
+    req: i32,
+) {
+    let sni = tls_field(req, |tls| tls.sni);
+    return_bytes(sni_ptr_p, sni_len_p, sni.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_get_tls_alpn(
+    alpn_ptr_p: *mut *mut u8,
+    alpn_len_p: *mut usize,
+    req: i32,
+) {
+    let alpn = tls_field(req, |tls| tls.alpn);
+    return_bytes(alpn_ptr_p, alpn_len_p, alpn.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_set_header(
+    req: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+    values_slice_ptr: *const GuestSlice<u8>,
+    values_slice_len: usize,
+) -> HostcallStatus {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = if values_slice_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(values_slice_ptr, values_slice_len)
+    };
+    with_host(|host| match host.requests.get_mut(&req) {
+        Some(r) if req != 0 => set_header(r.headers_mut(), name, values),
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_set_trailer(
+    req: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+    values_slice_ptr: *const GuestSlice<u8>,
+    values_slice_len: usize,
+) -> HostcallStatus {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = if values_slice_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(values_slice_ptr, values_slice_len)
+    };
+    with_host(|host| match host.requests.get_mut(&req) {
+        Some(r) if req != 0 => set_trailer(r.extensions_mut(), name, values),
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_set_body(
+    req: i32,
+    body_ptr: *const u8,
+    body_len: usize,
+) -> HostcallStatus {
+    let body = guest_bytes(body_ptr, body_len).to_vec();
+    with_host(|host| match host.requests.get_mut(&req) {
+        Some(r) if req != 0 => {
+            *r.body_mut() = body;
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_body_write(
+    req: i32,
+    buf_ptr: *const u8,
+    buf_len: usize,
+) -> HostcallStatus {
+    let buf = guest_bytes(buf_ptr, buf_len);
+    with_host(|host| match host.requests.get_mut(&req) {
+        Some(r) if req != 0 => {
+            r.body_mut().extend_from_slice(buf);
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_set_version(req: i32, version: u32) -> HostcallStatus {
+    let version = match HttpVersion::try_from_u32(version) {
+        Some(HttpVersion::Http09) => {
+            fail(ErrorCode::Protocol, "HTTP/0.9 requests are not supported");
+            return HostcallStatus::Invalid;
+        }
+        None => return HostcallStatus::Invalid,
+        Some(version) => version,
+    };
+    with_host(|host| match host.requests.get_mut(&req) {
+        Some(r) if req != 0 => {
+            *r.version_mut() = version.into();
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_req_set_timeouts(
+    req: i32,
+    connect_ms: u64,
+    total_ms: u64,
+) -> HostcallStatus {
+    let limit = |ms| {
+        if ms == 0 {
+            None
+        } else {
+            Some(Duration::from_millis(ms))
+        }
+    };
+    let timeouts = Timeouts {
+        connect: limit(connect_ms),
+        total: limit(total_ms),
+    };
+    with_host(|host| match host.requests.get_mut(&req) {
+        Some(r) if req != 0 => {
+            r.extensions_mut().insert(timeouts);
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_headers(
+    headers_ptr_p: *mut *mut GuestSlice<u8>,
+    headers_len_p: *mut usize,
+    resp: i32,
+) {
+    let names = with_host(|host| {
+        host.responses
+            .get(&resp)
+            .map(|resp| header_names(resp.headers()))
+            .unwrap_or_default()
+    });
+    return_slices(headers_ptr_p, headers_len_p, names);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_header(
+    values_ptr_p: *mut *mut GuestSlice<u8>,
+    values_len_p: *mut usize,
+    resp: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+) {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = with_host(|host| {
+        host.responses
+            .get(&resp)
+            .map(|resp| header_values(resp.headers(), name))
+            .unwrap_or_default()
+    });
+    return_slices(values_ptr_p, values_len_p, values);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_trailers(
+    trailers_ptr_p: *mut *mut GuestSlice<u8>,
+    trailers_len_p: *mut usize,
+    resp: i32,
+) {
+    let names = with_host(|host| {
+        host.responses
+            .get(&resp)
+            .map(|resp| header_names(&trailers(resp.extensions())))
+            .unwrap_or_default()
+    });
+    return_slices(trailers_ptr_p, trailers_len_p, names);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_trailer(
+    values_ptr_p: *mut *mut GuestSlice<u8>,
+    values_len_p: *mut usize,
+    resp: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+) {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = with_host(|host| {
+        host.responses
+            .get(&resp)
+            .map(|resp| header_values(&trailers(resp.extensions()), name))
+            .unwrap_or_default()
+    });
+    return_slices(values_ptr_p, values_len_p, values);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_body(
+    body_ptr_p: *mut *mut u8,
+    body_len_p: *mut usize,
+    resp: i32,
+) {
+    let body = with_host(|host| {
+        host.responses
+            .get(&resp)
+            .map(|resp| resp.body().clone())
+            .unwrap_or_default()
+    });
+    return_bytes(body_ptr_p, body_len_p, &body);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_body_read(
+    resp: i32,
+    buf_ptr: *mut u8,
+    buf_len: usize,
+    nread_p: *mut usize,
+) -> HostcallStatus {
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp != i32::from(ResponseHandle::OUTGOING) => {
+            read_body(r.body_mut(), buf_ptr, buf_len, nread_p)
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_response_code(resp: i32) -> u32 {
+    with_host(|host| {
+        host.responses
+            .get(&resp)
+            .map(|resp| u32::from(resp.status().as_u16()))
+            .unwrap_or(0)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_close(resp: i32) {
+    if resp != i32::from(ResponseHandle::OUTGOING) {
+        with_host(|host| {
+            host.responses.remove(&resp);
+            host.closed.push(resp);
+        });
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_version(resp: i32) -> u32 {
+    with_host(|host| {
+        let version = host
+            .responses
+            .get(&resp)
+            .map_or(http::Version::HTTP_11, |resp| resp.version());
+        HttpVersion::from(version) as u32
+    })
+}
+
+fn metadata(resp: i32) -> ResponseMetadata {
+    with_host(|host| {
+        host.responses
+            .get(&resp)
+            .and_then(|resp| resp.extensions().get::<ResponseMetadata>().cloned())
+            .unwrap_or_default()
+    })
+}
+
+fn duration_us(duration: Option<Duration>) -> u64 {
+    duration.map_or(u64::MAX, |d| {
+        d.as_secs()
+            .saturating_mul(1_000_000)
+            .saturating_add(u64::from(d.subsec_micros()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_timings(
+    dns_us_p: *mut u64,
+    connect_us_p: *mut u64,
+    tls_us_p: *mut u64,
+    first_byte_us_p: *mut u64,
+    total_us_p: *mut u64,
+    resp: i32,
+) {
+    let timings = metadata(resp).timings;
+    *dns_us_p = duration_us(timings.dns);
+    *connect_us_p = duration_us(timings.connect);
+    *tls_us_p = duration_us(timings.tls);
+    *first_byte_us_p = duration_us(timings.first_byte);
+    *total_us_p = duration_us(timings.total);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_remote_addr(
+    addr_ptr: *mut u8,
+    addr_len_p: *mut usize,
+    port_p: *mut u16,
+    resp: i32,
+) -> bool {
+    match metadata(resp).remote_addr {
+        Some(addr) => {
+            return_addr(addr, addr_ptr, addr_len_p, port_p);
+            true
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_get_tls_protocol(
+    protocol_ptr_p: *mut *mut u8,
+    protocol_len_p: *mut usize,
+    resp: i32,
+) {
+    let protocol = metadata(resp).tls_version.unwrap_or_default();
+    return_bytes(protocol_ptr_p, protocol_len_p, protocol.as_bytes());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_set_header(
+    resp: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+    values_ptr_p: *const GuestSlice<u8>,
+    values_len_p: usize,
+) -> HostcallStatus {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = if values_len_p == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(values_ptr_p, values_len_p)
+    };
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp == i32::from(ResponseHandle::OUTGOING) && !host.streaming => {
+            set_header(r.headers_mut(), name, values)
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+/// Trailers follow the body, so unlike headers they can still be set
+/// once the body has been streamed.
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_set_trailer(
+    resp: i32,
+    name_ptr: *const u8,
+    name_len: usize,
+    values_slice_ptr: *const GuestSlice<u8>,
+    values_slice_len: usize,
+) -> HostcallStatus {
+    let name = guest_bytes(name_ptr, name_len);
+    let values = if values_slice_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(values_slice_ptr, values_slice_len)
+    };
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp == i32::from(ResponseHandle::OUTGOING) => {
+            set_trailer(r.extensions_mut(), name, values)
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_set_body(
+    resp: i32,
+    body_ptr: *const u8,
+    body_len: usize,
+) -> HostcallStatus {
+    let body = guest_bytes(body_ptr, body_len).to_vec();
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp == i32::from(ResponseHandle::OUTGOING) && !host.streaming => {
+            *r.body_mut() = body;
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_body_write(
+    resp: i32,
+    buf_ptr: *const u8,
+    buf_len: usize,
+) -> HostcallStatus {
+    let buf = guest_bytes(buf_ptr, buf_len);
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp == i32::from(ResponseHandle::OUTGOING) => {
+            r.body_mut().extend_from_slice(buf);
+            host.streaming = true;
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_set_response_code(resp: i32, code: u16) -> HostcallStatus {
+    let code = match StatusCode::from_u16(code) {
+        Ok(code) => code,
+        Err(_) => return HostcallStatus::Invalid,
+    };
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp == i32::from(ResponseHandle::OUTGOING) && !host.streaming => {
+            *r.status_mut() = code;
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_set_version(resp: i32, version: u32) -> HostcallStatus {
+    let version = match HttpVersion::try_from_u32(version) {
+        Some(version) => version,
+        None => return HostcallStatus::Invalid,
+    };
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp == i32::from(ResponseHandle::OUTGOING) && !host.streaming => {
+            *r.version_mut() = version.into();
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_resp_set_reason(
+    resp: i32,
+    reason_ptr: *const u8,
+    reason_len: usize,
+) -> HostcallStatus {
+    let reason = guest_str(reason_ptr, reason_len);
+    with_host(|host| match host.responses.get_mut(&resp) {
+        Some(r) if resp == i32::from(ResponseHandle::OUTGOING) && !host.streaming => {
+            r.extensions_mut().insert(ReasonPhrase(reason));
+            HostcallStatus::Ok
+        }
+        _ => HostcallStatus::Invalid,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_kvstore_insert(
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+) -> bool {
+    let key = guest_str(key_ptr, key_len);
+    let value = guest_bytes(value_ptr, value_len).to_vec();
+    with_host(|host| host.kvstore.insert(key, value).is_none())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_kvstore_upsert(
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+) -> bool {
+    let key = guest_str(key_ptr, key_len);
+    let value = guest_bytes(value_ptr, value_len).to_vec();
+    with_host(|host| match host.kvstore.entry(key) {
+        Entry::Occupied(_) => false,
+        Entry::Vacant(entry) => {
+            entry.insert(value);
+            true
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_kvstore_append(
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+) -> bool {
+    let key = guest_str(key_ptr, key_len);
+    let value = guest_bytes(value_ptr, value_len);
+    with_host(|host| match host.kvstore.get_mut(&key) {
+        Some(existing) => {
+            existing.extend_from_slice(value);
+            false
+        }
+        None => {
+            host.kvstore.insert(key, value.to_vec());
+            true
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_kvstore_get(
+    value_ptr_p: *mut *mut u8,
+    value_len_p: *mut usize,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> bool {
+    let key = guest_str(key_ptr, key_len);
+    match with_host(|host| host.kvstore.get(&key).cloned()) {
+        Some(value) => {
+            // the guest asserts the pointer is non-null whenever the key
+            // is found, even for an empty value
+            *value_ptr_p = with_host(|host| host.alloc_copy(&value));
+            *value_len_p = value.len();
+            true
+        }
+        None => {
+            *value_ptr_p = ptr::null_mut();
+            *value_len_p = 0;
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_kvstore_remove(key_ptr: *const u8, key_len: usize) -> bool {
+    let key = guest_str(key_ptr, key_len);
+    with_host(|host| host.kvstore.remove(&key).is_some())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_panic_hook(msg_ptr: *const u8, msg_len: usize) {
+    let msg = guest_str(msg_ptr, msg_len);
+    // the guest's hook replaces the default one, so print the message
+    // to keep it visible in test output
+    eprintln!("{}", msg);
+    with_host(|host| host.panic_log.push(msg));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_init_mm(
+    malloc_impl: extern "C" fn(size: usize) -> *mut c_void,
+    _free_impl: extern "C" fn(ptr: *mut c_void),
+) {
+    with_host(|host| host.set_malloc(malloc_impl));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_rng_next_u64() -> u64 {
+    with_host(|host| host.next_u64())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_time_now(subsec_nanos_p: *mut u32) -> u64 {
+    let now = with_host(|host| host.now);
+    *subsec_nanos_p = now.subsec_nanos();
+    now.as_secs()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_time_sleep(duration_ms: u64) {
+    with_host(|host| host.now += Duration::from_millis(duration_ms));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_dns_query_raw(
+    response_ptr_p: *mut *mut u8,
+    response_len_p: *mut usize,
+    query_ptr: *const u8,
+    query_len: usize,
+) -> bool {
+    let query = guest_bytes(query_ptr, query_len);
+    match with_host(|host| host.dns_raw.get(query).cloned()) {
+        Some(response) if !response.is_empty() => {
+            return_bytes(response_ptr_p, response_len_p, &response);
+            true
+        }
+        _ => {
+            *response_ptr_p = ptr::null_mut();
+            *response_len_p = 0;
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_dns_query_ip(
+    responses_ptr_p: *mut *mut GuestSlice<u8>,
+    responses_len_p: *mut usize,
+    name_ptr: *const u8,
+    name_len: usize,
+    ipv6: bool,
+) -> bool {
+    let name = guest_str(name_ptr, name_len);
+    let addrs = with_host(|host| host.dns_ips.get(&name).cloned().unwrap_or_default())
+        .into_iter()
+        .filter(|addr| addr.is_ipv6() == ipv6)
+        .map(|addr| match addr {
+            std::net::IpAddr::V4(addr) => addr.octets().to_vec(),
+            std::net::IpAddr::V6(addr) => addr.octets().to_vec(),
+        })
+        .collect::<Vec<Vec<u8>>>();
+    return_slices(responses_ptr_p, responses_len_p, &addrs);
+    !addrs.is_empty()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hostcall_debug(msg_ptr: *const u8, msg_len: usize) {
+    let msg = guest_str(msg_ptr, msg_len);
+    with_host(|host| host.debug_log.push(msg));
+}
@@ -0,0 +1,473 @@
+//! An in-process mock of the Terrarium host, for testing guest
+//! applications natively with `cargo test`.
+//!
+//! Enabling the `mock` feature links native definitions of every
+//! hostcall into the crate, backed by per-thread Rust data structures
+//! in place of the runtime. The `run`, `run_kvs`, `run_result`,
+//! `run_async` and `run_streaming` harnesses then drive an entrypoint
+//! the same way the `guest_app` macros do, and return the response it
+//! produced:
+//!
+//! ```text
+//! use http_guest::{mock, Request, Response};
+//!
+//! fn user_entrypoint(req: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+//!     Response::builder().status(200).body(req.body().clone()).unwrap()
+//! }
+//!
+//! #[test]
+//! fn echoes_body() {
+//!     let req = Request::post("/").body(b"hi".to_vec()).unwrap();
+//!     let resp = mock::run(req, user_entrypoint);
+//!     assert_eq!(resp.body(), b"hi");
+//! }
+//! ```
+//!
+//! Outbound requests made with `RequestExt` go to the function set
+//! with `set_upstream`, or to a `FakeUpstream` scripted with canned
+//! replies and latencies set with `set_fake_upstream`. The key-value store,
+//! DNS, random number generator and clock can likewise be inspected
+//! or primed through the functions in this module.
+
+mod host;
+mod hostcalls;
+mod upstream;
+
+pub use crate::mock::upstream::{FakeUpstream, Reply};
+
+use http::{Request, Response};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Read;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::error::ErrorResponse;
+use crate::hostcalls::RequestHandle;
+use crate::kvstore::KVStore;
+use crate::mock::host::with_host;
+use crate::routing::Handler;
+use crate::scaffolding::Stack;
+use crate::trailers::Trailers;
+
+/// Run a `guest_app` entrypoint against `req`, returning the response
+/// it produces.
+///
+/// A `ClientInfo` in the extensions of `req` is reported as the
+/// client connection; without one, the client address and TLS session
+/// are unknown.
+pub fn run<H: Handler>(req: Request<Vec<u8>>, user_entrypoint: H) -> Response<Vec<u8>> {
+    run_with(req, user_entrypoint, Stack::new())
+}
+
+/// Run a `guest_app` entrypoint with a stack of middleware against
+/// `req`, returning the response it produces.
+pub fn run_with<H: Handler>(
+    req: Request<Vec<u8>>,
+    user_entrypoint: H,
+    middleware: Stack,
+) -> Response<Vec<u8>> {
+    start(req);
+    crate::scaffolding::raw_entrypoint_with(user_entrypoint, middleware);
+    finish()
+}
+
+/// Run a `guest_app_kvs` entrypoint against `req`, returning the
+/// response it produces.
+///
+/// The key-value store persists between runs on the same thread, as
+/// it does between requests in the runtime.
+pub fn run_kvs<F>(req: Request<Vec<u8>>, user_entrypoint: F) -> Response<Vec<u8>>
+where
+    F: Fn(&mut KVStore, &Request<Vec<u8>>) -> Response<Vec<u8>>,
+{
+    run_kvs_with(req, user_entrypoint, Stack::new())
+}
+
+/// Run a `guest_app_kvs` entrypoint with a stack of middleware against
+/// `req`, returning the response it produces.
+pub fn run_kvs_with<F>(
+    req: Request<Vec<u8>>,
+    user_entrypoint: F,
+    middleware: Stack,
+) -> Response<Vec<u8>>
+where
+    F: Fn(&mut KVStore, &Request<Vec<u8>>) -> Response<Vec<u8>>,
+{
+    start(req);
+    crate::scaffolding::raw_entrypoint_kvs_with(user_entrypoint, middleware);
+    finish()
+}
+
+/// Run a `guest_app_result` entrypoint against `req`, returning the
+/// response it produces, or the one rendered from its error.
+pub fn run_result<F, R, E>(req: Request<Vec<u8>>, user_entrypoint: F) -> Response<Vec<u8>>
+where
+    F: Fn(&Request<Vec<u8>>) -> Result<R, E>,
+    R: Into<Response<Vec<u8>>>,
+    E: ErrorResponse,
+{
+    start(req);
+    crate::scaffolding::raw_entrypoint_result(user_entrypoint);
+    finish()
+}
+
+/// Run a `guest_app_async` entrypoint against `req`, returning the
+/// response it produces.
+pub fn run_async<F, Fut>(req: Request<Vec<u8>>, user_entrypoint: F) -> Response<Vec<u8>>
+where
+    F: Fn(Request<Vec<u8>>) -> Fut,
+    Fut: Future<Output = Response<Vec<u8>>>,
+{
+    start(req);
+    crate::scaffolding::raw_entrypoint_async(user_entrypoint);
+    finish()
+}
+
+/// Run a `guest_app_streaming` entrypoint against `req`, returning the
+/// response it produces with the streamed body collected.
+pub fn run_streaming<F, B>(req: Request<Vec<u8>>, user_entrypoint: F) -> Response<Vec<u8>>
+where
+    F: Fn(Request<RequestHandle>) -> Response<B>,
+    B: Read,
+{
+    start(req);
+    crate::scaffolding::raw_entrypoint_streaming(user_entrypoint);
+    finish()
+}
+
+fn start(req: Request<Vec<u8>>) {
+    with_host(|host| host.start(req));
+    crate::panic::panic_set_once();
+    crate::guest_allocator::init_mm_default();
+}
+
+/// Take the response the guest produced. A custom reason phrase is
+/// returned as a `ReasonPhrase` in its extensions, and any trailers as
+/// `Trailers`.
+fn finish() -> Response<Vec<u8>> {
+    with_host(|host| host.responses.remove(&0))
+        .expect("outgoing response is present until the run finishes")
+}
+
+/// Forget every pending request, as if the host had lost track of them, for the crate's own
+/// tests of host failures.
+#[cfg(test)]
+pub(crate) fn forget_pending_requests() {
+    with_host(|host| host.pending.clear());
+}
+
+/// Run `f` as the body of an entrypoint, for the crate's own tests.
+///
+/// The scaffolding turns a panicking entrypoint into a `500`, so this
+/// fails the calling test instead if `f` panics.
+#[cfg(test)]
+pub(crate) fn guest<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    use std::cell::RefCell;
+
+    let f = RefCell::new(Some(f));
+    let out = RefCell::new(None);
+    run(Request::new(vec![]), |_: &Request<Vec<u8>>| {
+        let f = f.borrow_mut().take().expect("entrypoint runs once");
+        *out.borrow_mut() = Some(f());
+        Response::new(vec![])
+    });
+    let panics = panic_messages();
+    assert!(panics.is_empty(), "guest panicked: {:?}", panics);
+    out.into_inner().expect("entrypoint ran")
+}
+
+/// Every handle the guest closed during the last run, in order.
+#[cfg(test)]
+pub(crate) fn closed_handles() -> Vec<i32> {
+    with_host(|host| host.closed.clone())
+}
+
+/// Every pending request the guest cancelled during the last run, in
+/// order.
+#[cfg(test)]
+pub(crate) fn cancelled_handles() -> Vec<i32> {
+    with_host(|host| host.cancelled.clone())
+}
+
+/// Discard all state held by the mock host on this thread, including
+/// the key-value store and any configured upstream.
+pub fn reset() {
+    with_host(|host| *host = Default::default());
+}
+
+/// Set the function that answers outbound requests.
+///
+/// Returning `None` makes the request fail, as a connection error
+/// would in the runtime. Without an upstream, every outbound request
+/// fails.
+pub fn set_upstream<F>(upstream: F)
+where
+    F: Fn(&Request<Vec<u8>>) -> Option<Response<Vec<u8>>> + 'static,
+{
+    with_host(|host| host.upstream = Some(Box::new(upstream)));
+}
+
+/// Answer outbound requests from the routes of a `FakeUpstream`,
+/// replacing any function set with `set_upstream`.
+pub fn set_fake_upstream(upstream: FakeUpstream) {
+    with_host(|host| host.upstream = Some(Box::new(upstream)));
+}
+
+/// Copies of the outbound requests sent during the last run, in the
+/// order they were sent.
+///
+/// Extensions are not copied, other than any `Trailers` the request
+/// was sent with.
+pub fn sent_requests() -> Vec<Request<Vec<u8>>> {
+    with_host(|host| {
+        host.sent
+            .iter()
+            .map(|req| {
+                let mut copy = Request::new(req.body().clone());
+                *copy.method_mut() = req.method().clone();
+                *copy.uri_mut() = req.uri().clone();
+                *copy.version_mut() = req.version();
+                *copy.headers_mut() = req.headers().clone();
+                if let Some(trailers) = req.extensions().get::<Trailers>() {
+                    copy.extensions_mut().insert(trailers.clone());
+                }
+                copy
+            })
+            .collect()
+    })
+}
+
+/// The number of request, response and pending request handles that
+/// the guest has open, not counting the incoming request and the
+/// outgoing response.
+///
+/// Handles are closed when dropped, so once a run has finished this
+/// should be zero unless the guest leaked a handle with `mem::forget`
+/// or by converting it into an `i32`.
+pub fn open_handles() -> usize {
+    with_host(|host| {
+        host.requests.keys().filter(|&&h| h != 0).count()
+            + host.responses.keys().filter(|&&h| h != 0).count()
+            + host.pending.len()
+    })
+}
+
+/// Get a copy of the contents of the key-value store.
+pub fn kvstore() -> HashMap<String, Vec<u8>> {
+    with_host(|host| host.kvstore.clone())
+}
+
+/// Set a value in the key-value store before running the guest.
+pub fn kvstore_insert(key: &str, value: &[u8]) {
+    with_host(|host| host.kvstore.insert(key.to_owned(), value.to_vec()));
+}
+
+/// Set the addresses returned by `DNS::query_ip` for `name`.
+pub fn set_dns_ips(name: &str, addrs: &[IpAddr]) {
+    with_host(|host| host.dns_ips.insert(name.to_owned(), addrs.to_vec()));
+}
+
+/// Set the response returned by `DNS::query_raw` for `query`.
+pub fn set_dns_raw(query: &[u8], response: &[u8]) {
+    with_host(|host| host.dns_raw.insert(query.to_vec(), response.to_vec()));
+}
+
+/// Reseed the random number generator behind `rand::guest_rng`.
+pub fn seed_rng(seed: u64) {
+    with_host(|host| host.rng_state = seed);
+}
+
+/// Set the time returned by `Time::since_epoch`.
+///
+/// The mock clock only advances when told to, or when the guest waits
+/// on an upstream reply with latency.
+pub fn set_time(since_epoch: Duration) {
+    with_host(|host| host.now = since_epoch);
+}
+
+/// Move the mock clock forward.
+pub fn advance_time(by: Duration) {
+    with_host(|host| host.now += by);
+}
+
+/// Messages passed to `hostcalls::debug` during the last run.
+pub fn debug_messages() -> Vec<String> {
+    with_host(|host| host.debug_log.clone())
+}
+
+/// Messages reported by the panic hook during the last run.
+pub fn panic_messages() -> Vec<String> {
+    with_host(|host| host.panic_log.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::DNS;
+    use crate::hostcalls::RequestHandle;
+    use crate::rand::guest_rng;
+    use crate::test_util::get;
+    use crate::time::Time;
+    use rand_core::RngCore;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn empty() -> Response<Vec<u8>> {
+        Response::new(vec![])
+    }
+
+    #[test]
+    fn runs_entrypoint_against_request() {
+        let req = Request::post("/echo").body(b"hi".to_vec()).unwrap();
+        let resp = run(req, |req: &Request<Vec<u8>>| {
+            Response::builder()
+                .status(201)
+                .header("x-echo", "yes")
+                .body(req.body().clone())
+                .unwrap()
+        });
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers()["x-echo"], "yes");
+        assert_eq!(resp.body(), b"hi");
+    }
+
+    #[test]
+    fn panicking_entrypoint_is_reported() {
+        let resp = run(get("/"), |_: &Request<Vec<u8>>| -> Response<Vec<u8>> {
+            panic!("boom")
+        });
+        assert_eq!(resp.status(), 500);
+        assert_eq!(panic_messages().len(), 1);
+        assert!(panic_messages()[0].contains("boom"));
+    }
+
+    #[test]
+    fn handles_are_allocated_in_order_and_closed_on_drop() {
+        guest(|| {
+            let a = RequestHandle::create("GET", "http://a/").unwrap();
+            let b = RequestHandle::create("GET", "http://b/").unwrap();
+            assert_eq!((i32::from(&a), i32::from(&b)), (1, 2));
+            assert_eq!(open_handles(), 2);
+            drop(a);
+            assert_eq!(open_handles(), 1);
+            drop(b);
+        });
+        assert_eq!(open_handles(), 0);
+    }
+
+    #[test]
+    fn leaked_handles_stay_open_until_the_next_run() {
+        run(get("/"), |_: &Request<Vec<u8>>| {
+            mem::forget(RequestHandle::create("GET", "http://a/").unwrap());
+            let _ = i32::from(RequestHandle::create("GET", "http://b/").unwrap());
+            empty()
+        });
+        assert_eq!(open_handles(), 2);
+        guest(|| {
+            // numbering starts again for each run
+            let req = RequestHandle::create("GET", "http://a/").unwrap();
+            assert_eq!(i32::from(&req), 1);
+        });
+        assert_eq!(open_handles(), 0);
+    }
+
+    #[test]
+    fn invalid_request_gets_no_handle() {
+        guest(|| {
+            assert!(RequestHandle::create("GET", "/relative").is_none());
+            assert!(RequestHandle::create("BAD METHOD", "http://a/").is_none());
+            assert_eq!(open_handles(), 0);
+        });
+    }
+
+    #[test]
+    fn kvstore_is_primed_and_persists_between_runs() {
+        kvstore_insert("count", b"1");
+        let entrypoint = |store: &mut KVStore, _: &Request<Vec<u8>>| {
+            let count = store.get("count").unwrap();
+            let next = String::from_utf8(count).unwrap().parse::<u32>().unwrap() + 1;
+            store.insert("count", next.to_string().as_bytes());
+            Response::new(next.to_string().into_bytes())
+        };
+        assert_eq!(run_kvs(get("/"), entrypoint).body(), b"2");
+        assert_eq!(run_kvs(get("/"), entrypoint).body(), b"3");
+        assert_eq!(kvstore()["count"], b"3");
+
+        reset();
+        assert!(kvstore().is_empty());
+    }
+
+    #[test]
+    fn kvstore_operations() {
+        guest(|| {
+            let mut store = KVStore::global();
+            assert!(store.insert("a", b"1"));
+            assert!(!store.upsert("a", b"2"));
+            assert_eq!(store.get("a").unwrap(), b"1");
+            assert!(!store.append("a", b"2"));
+            assert_eq!(store.get("a").unwrap(), b"12");
+            assert!(store.remove("a"));
+            assert!(!store.remove("a"));
+            assert_eq!(store.get("a"), None);
+        });
+    }
+
+    #[test]
+    fn dns_is_primed() {
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        set_dns_ips("example.com", &[v4, v6]);
+        set_dns_raw(b"query", b"answer");
+        guest(|| {
+            assert_eq!(DNS::query_ip("example.com", false).unwrap(), vec![v4]);
+            assert_eq!(DNS::query_ip("example.com", true).unwrap(), vec![v6]);
+            assert!(DNS::query_ip("unknown.com", false).is_err());
+            assert_eq!(DNS::query_raw(b"query").unwrap(), b"answer");
+            assert!(DNS::query_raw(b"other").is_err());
+        });
+    }
+
+    #[test]
+    fn rng_is_deterministic_for_a_seed() {
+        let draw = || {
+            guest(|| {
+                let mut rng = guest_rng();
+                [rng.next_u64(), rng.next_u64()]
+            })
+        };
+        seed_rng(42);
+        let first = draw();
+        seed_rng(42);
+        assert_eq!(draw(), first);
+        seed_rng(43);
+        assert_ne!(draw(), first);
+    }
+
+    #[test]
+    fn clock_is_primed_and_only_advances_when_told() {
+        // `coarsetime` only keeps fractions of a second to within a few nanoseconds
+        let close_to = |t: coarsetime::Duration, secs: f64| (t.as_f64() - secs).abs() < 1e-6;
+        set_time(Duration::new(1_000, 500_000_000));
+        advance_time(Duration::from_secs(5));
+        guest(|| {
+            let now = Time::since_epoch();
+            assert!(close_to(now, 1_005.5));
+            assert_eq!(Time::since_epoch(), now);
+            Time::sleep(Duration::from_millis(250));
+            assert!(close_to(Time::since_epoch(), 1_005.75));
+        });
+        with_host(|host| assert_eq!(host.now, Duration::new(1_005, 750_000_000)));
+    }
+
+    #[test]
+    fn debug_messages_are_recorded_per_run() {
+        guest(|| crate::hostcalls::debug("hello"));
+        assert_eq!(debug_messages(), vec!["hello".to_owned()]);
+        run(get("/"), |_: &Request<Vec<u8>>| empty());
+        assert!(debug_messages().is_empty());
+    }
+}
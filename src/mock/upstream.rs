@@ -0,0 +1,330 @@
+//! Scripted upstreams for the mock host.
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::{Method, Request, Response, StatusCode, Version};
+use std::time::Duration;
+
+use crate::client::{ResponseMetadata, Timings};
+use crate::hostcalls::types::ErrorCode;
+use crate::mock::host::Outcome;
+use crate::trailers::Trailers;
+
+/// Something that answers the outbound requests made by the guest.
+pub(crate) trait Upstream {
+    /// Decide how to answer an outbound request.
+    fn reply(&self, req: &Request<Vec<u8>>) -> Reply;
+}
+
+impl<F> Upstream for F
+where
+    F: Fn(&Request<Vec<u8>>) -> Option<Response<Vec<u8>>>,
+{
+    fn reply(&self, req: &Request<Vec<u8>>) -> Reply {
+        match self(req) {
+            Some(resp) => Reply::from(resp),
+            None => Reply::failure(),
+        }
+    }
+}
+
+/// A canned answer to an outbound request.
+///
+/// Replies take effect on the mock clock: a request sent with
+/// `send()` advances it by the reply's latency, and one sent with
+/// `send_async()` only becomes ready once the clock has passed its
+/// latency. This is what determines the order in which `select`
+/// returns pending requests.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    status: StatusCode,
+    headers: HeaderMap,
+    trailers: HeaderMap,
+    body: Vec<u8>,
+    latency: Duration,
+    version: Option<Version>,
+    metadata: Option<ResponseMetadata>,
+    error: Option<(ErrorCode, String)>,
+}
+
+impl Reply {
+    /// A successful reply with the given status code.
+    ///
+    /// Panics if `code` is not a valid status code.
+    pub fn status(code: u16) -> Reply {
+        Reply {
+            status: StatusCode::from_u16(code).expect("valid status code"),
+            headers: HeaderMap::new(),
+            trailers: HeaderMap::new(),
+            body: vec![],
+            latency: Duration::from_secs(0),
+            version: None,
+            metadata: None,
+            error: None,
+        }
+    }
+
+    /// A reply that makes the request fail with a connection error.
+    pub fn failure() -> Reply {
+        Reply::error(ErrorCode::Connect, "connection refused")
+    }
+
+    /// A reply that makes the request fail, with the code and message
+    /// that the guest sees through `hostcalls::last_error`.
+    pub fn error(code: ErrorCode, message: &str) -> Reply {
+        Reply {
+            error: Some((code, message.to_owned())),
+            ..Reply::status(500)
+        }
+    }
+
+    /// Add a header to the reply. The value need not be UTF-8.
+    ///
+    /// Panics if the name or value are not valid in a header.
+    pub fn header<V: AsRef<[u8]>>(mut self, name: &str, value: V) -> Reply {
+        self.headers.append(
+            HeaderName::from_bytes(name.as_bytes()).expect("valid header name"),
+            HeaderValue::from_bytes(value.as_ref()).expect("valid header value"),
+        );
+        self
+    }
+
+    /// Add a trailer to the reply.
+    ///
+    /// Panics if the name or value are not valid in a header.
+    pub fn trailer<V: AsRef<[u8]>>(mut self, name: &str, value: V) -> Reply {
+        self.trailers.append(
+            HeaderName::from_bytes(name.as_bytes()).expect("valid header name"),
+            HeaderValue::from_bytes(value.as_ref()).expect("valid header value"),
+        );
+        self
+    }
+
+    /// Set the body of the reply.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Reply {
+        self.body = body.into();
+        self
+    }
+
+    /// Delay the reply by `latency` on the mock clock.
+    ///
+    /// A request whose total timeout is shorter than this fails with
+    /// `ErrorCode::Timeout` once the timeout has passed. Connect
+    /// timeouts are not enforced by the mock.
+    pub fn latency(mut self, latency: Duration) -> Reply {
+        self.latency = latency;
+        self
+    }
+
+    /// Respond with the given HTTP version, rather than the one the
+    /// request was sent with.
+    pub fn version(mut self, version: Version) -> Reply {
+        self.version = Some(version);
+        self
+    }
+
+    /// Report the given timings, remote address and TLS version for
+    /// the reply. Without this, the reply's latency is reported as its
+    /// time to first byte and total time, and nothing else is known.
+    pub fn metadata(mut self, metadata: ResponseMetadata) -> Reply {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub(crate) fn get_latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Build the response for this reply to a request sent with
+    /// `version`, or the error it fails with.
+    pub(crate) fn to_response(&self, version: Version) -> Outcome {
+        if let Some(ref error) = self.error {
+            return Err(error.clone());
+        }
+        let mut resp = Response::new(self.body.clone());
+        *resp.status_mut() = self.status;
+        *resp.version_mut() = self.version.unwrap_or(version);
+        *resp.headers_mut() = self.headers.clone();
+        let metadata = self.metadata.clone().unwrap_or_else(|| ResponseMetadata {
+            timings: Timings {
+                first_byte: Some(self.latency),
+                total: Some(self.latency),
+                ..Timings::default()
+            },
+            ..ResponseMetadata::default()
+        });
+        resp.extensions_mut().insert(metadata);
+        if !self.trailers.is_empty() {
+            resp.extensions_mut()
+                .insert(Trailers(self.trailers.clone()));
+        }
+        Ok(resp)
+    }
+}
+
+impl From<Response<Vec<u8>>> for Reply {
+    fn from(resp: Response<Vec<u8>>) -> Reply {
+        let (parts, body) = resp.into_parts();
+        let trailers = parts
+            .extensions
+            .get::<Trailers>()
+            .map(|trailers| trailers.0.clone())
+            .unwrap_or_default();
+        Reply {
+            status: parts.status,
+            headers: parts.headers,
+            trailers,
+            body,
+            latency: Duration::from_secs(0),
+            version: Some(parts.version),
+            metadata: parts.extensions.get::<ResponseMetadata>().cloned(),
+            error: None,
+        }
+    }
+}
+
+struct Route {
+    method: Option<Method>,
+    pattern: String,
+    reply: Reply,
+}
+
+/// An upstream that answers requests from a list of routes.
+///
+/// Routes are tried in the order they were added, and the first one
+/// whose method and URL pattern match the request supplies the reply.
+/// Patterns are full URLs in which `*` matches any run of characters,
+/// for example `http://example.com/users/*`. Requests that match no
+/// route fail.
+///
+/// ```text
+/// mock::set_fake_upstream(
+///     FakeUpstream::new()
+///         .route(Method::GET, "http://users/*", Reply::status(200).body("[]"))
+///         .route_any("http://slow/*", Reply::status(200).latency(Duration::from_millis(50)))
+///         .route_any("http://down/*", Reply::failure()),
+/// );
+/// ```
+#[derive(Default)]
+pub struct FakeUpstream {
+    routes: Vec<Route>,
+}
+
+impl FakeUpstream {
+    pub fn new() -> FakeUpstream {
+        FakeUpstream::default()
+    }
+
+    /// Answer requests with the given method and a URL matching
+    /// `pattern`.
+    pub fn route(mut self, method: Method, pattern: &str, reply: Reply) -> FakeUpstream {
+        self.routes.push(Route {
+            method: Some(method),
+            pattern: pattern.to_owned(),
+            reply,
+        });
+        self
+    }
+
+    /// Answer requests with any method and a URL matching `pattern`.
+    pub fn route_any(mut self, pattern: &str, reply: Reply) -> FakeUpstream {
+        self.routes.push(Route {
+            method: None,
+            pattern: pattern.to_owned(),
+            reply,
+        });
+        self
+    }
+}
+
+impl Upstream for FakeUpstream {
+    fn reply(&self, req: &Request<Vec<u8>>) -> Reply {
+        let url = req.uri().to_string();
+        self.routes
+            .iter()
+            .find(|route| {
+                route.method.iter().all(|m| m == req.method())
+                    && wildcard_match(&route.pattern, &url)
+            })
+            .map(|route| route.reply.clone())
+            .unwrap_or_else(|| Reply::error(ErrorCode::Connect, &format!("no route to {}", url)))
+    }
+}
+
+/// Match `text` against `pattern`, where `*` in the pattern matches any
+/// run of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !text.starts_with(first) {
+        return false;
+    }
+    let mut rest = &text[first.len()..];
+    let parts = parts.collect::<Vec<&str>>();
+    match parts.split_last() {
+        // no `*` in the pattern, so it must match exactly
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_match() {
+        assert!(wildcard_match("http://up/a", "http://up/a"));
+        assert!(!wildcard_match("http://up/a", "http://up/ab"));
+        assert!(!wildcard_match("http://up/ab", "http://up/a"));
+        assert!(wildcard_match("", ""));
+    }
+
+    #[test]
+    fn star_in_the_middle() {
+        assert!(wildcard_match("http://*/a", "http://up/a"));
+        assert!(wildcard_match("http://*/a", "http:///a"));
+        assert!(wildcard_match("http://*/a", "http://up/x/a"));
+        assert!(!wildcard_match("http://*/a", "http://up/a/b"));
+        assert!(wildcard_match("a*b*c", "a-b-b-c"));
+    }
+
+    #[test]
+    fn star_at_the_end() {
+        assert!(wildcard_match("http://up/*", "http://up/"));
+        assert!(wildcard_match("http://up/*", "http://up/a/b?c=d"));
+        assert!(!wildcard_match("http://up/*", "http://down/a"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "anything"));
+    }
+
+    #[test]
+    fn empty_segments() {
+        assert!(wildcard_match("a**b", "ab"));
+        assert!(wildcard_match("a**b", "a-b"));
+        assert!(wildcard_match("**", ""));
+        assert!(!wildcard_match("a**b", "a-c"));
+    }
+
+    #[test]
+    fn segments_do_not_overlap() {
+        // the prefix and suffix cannot share the same characters
+        assert!(!wildcard_match("ab*ba", "aba"));
+        assert!(wildcard_match("ab*ba", "abba"));
+        assert!(!wildcard_match("a*a*a", "aa"));
+    }
+
+    #[test]
+    fn no_match() {
+        assert!(!wildcard_match("http://up/*", ""));
+        assert!(!wildcard_match("http://up/a", "https://up/a"));
+        assert!(!wildcard_match("*.example.com", "example.org"));
+    }
+}
@@ -3,16 +3,65 @@
 //! Based on code from the
 //! [`console_error_panic_hook`](https://github.com/rustwasm/console_error_panic_hook)
 //! crate.
+//!
+//! The scaffolding catches a panic in the entrypoint and sends a
+//! fallback response in its place, which requires the guest to be
+//! built to unwind. With `panic = "abort"`, which is how the standard
+//! library for `wasm32-unknown-unknown` is prebuilt, the instance traps
+//! as soon as the panic has been reported to the host, and no response
+//! is sent. The crate documentation describes the toolchain setup that
+//! unwinding needs on that target.
 
-use std::panic;
+use http::{Response, StatusCode};
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
 
 use crate::hostcalls::panic_hook;
 
+thread_local! {
+    static PANIC_RESPONSE: Cell<fn() -> Response<Vec<u8>>> = Cell::new(default_panic_response);
+}
+
 /// A panic hook for use with the demo hostcall interface.
+///
+/// This reports the message and location of every panic to the host,
+/// including those that the scaffolding goes on to catch.
 pub fn hook(info: &panic::PanicInfo) {
     panic_hook(&info.to_string());
 }
 
+/// Set the function that builds the response sent to the client when
+/// the entrypoint panics, which by default is an empty `500 Internal
+/// Server Error`.
+///
+/// This is meant to be used by the scaffolding macros, which call it
+/// with their `on_panic` argument before running the entrypoint. We
+/// hide the doc so that there is only one place to configure the
+/// fallback, but it needs to be exported so the macros can work.
+#[doc(hidden)]
+pub fn set_panic_response(fallback: fn() -> Response<Vec<u8>>) {
+    PANIC_RESPONSE.with(|f| f.set(fallback));
+}
+
+fn default_panic_response() -> Response<Vec<u8>> {
+    let mut resp = Response::new(vec![]);
+    *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    resp
+}
+
+/// Build the response sent to the client when the entrypoint panics.
+pub(crate) fn panic_response() -> Response<Vec<u8>> {
+    PANIC_RESPONSE.with(|f| f.get())()
+}
+
+/// Run the user entrypoint, returning `None` if it panics.
+pub(crate) fn catch_panic<F, T>(f: F) -> Option<T>
+where
+    F: FnOnce() -> T,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).ok()
+}
+
 /// Set the hostcall panic hook the first time this is
 /// called; subsequent invocations do nothing.
 ///
@@ -0,0 +1,384 @@
+//! Routing requests to handlers by method and path.
+
+use http::header::{self, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+
+use crate::extract::percent_decode;
+
+/// Something that produces a response for a request.
+///
+/// This is what `guest_app!` and `Router` accept. It is implemented for
+/// functions and closures taking `&Request<Vec<u8>>`, and for `Router`
+/// itself.
+pub trait Handler {
+    fn handle(&self, req: &Request<Vec<u8>>) -> Response<Vec<u8>>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request<Vec<u8>>) -> Response<Vec<u8>>,
+{
+    fn handle(&self, req: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+        self(req)
+    }
+}
+
+/// The parameters captured from the path of a request by a route's
+/// template.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// Get the value captured for the parameter `name`, percent-decoded.
+    ///
+    /// An unnamed wildcard is captured as `"*"`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Iterate over the captured parameters in the order they appear in
+    /// the template.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+type RouteHandler = Box<dyn Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>>>;
+
+struct Route {
+    method: Method,
+    template: Vec<Segment>,
+    handler: RouteHandler,
+}
+
+/// Dispatches requests to handlers by method and path template.
+///
+/// A template is a path whose segments are matched literally, except
+/// for `:name`, which matches any one segment, and `*name` or `*`,
+/// which may only come last and matches the rest of the path. The
+/// segments they match are percent-decoded and passed to the handler as
+/// `Params`. Literal segments are compared with the path as it was
+/// sent, without decoding it. Routes are tried in the order they were
+/// added.
+///
+/// A `HEAD` request that matches no `HEAD` route is handled by the
+/// first matching `GET` route, with the body of its response removed.
+///
+/// A request whose path matches no route gets a `404 Not Found`, and
+/// one whose path matches only routes for other methods gets a `405
+/// Method Not Allowed` with an `Allow` header listing them.
+///
+/// ```text
+/// #[macro_use]
+/// extern crate http_guest;
+///
+/// use http_guest::{Params, Request, Response, Router};
+///
+/// fn get_user(_req: &Request<Vec<u8>>, params: &Params) -> Response<Vec<u8>> {
+///     let id = params.get("id").unwrap();
+///     Response::builder().status(200).body(id.as_bytes().to_owned()).unwrap()
+/// }
+///
+/// fn router() -> Router {
+///     Router::new()
+///         .get("/users/:id", get_user)
+///         .get("/static/*path", serve_static)
+/// }
+///
+/// guest_app!(router());
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Add a route for requests with the given method and a path
+    /// matching `template`.
+    ///
+    /// Panics if a wildcard is followed by more segments.
+    pub fn route<F>(mut self, method: Method, template: &str, handler: F) -> Router
+    where
+        F: Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>> + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            template: parse_template(template),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Add a route for `GET` requests.
+    pub fn get<F>(self, template: &str, handler: F) -> Router
+    where
+        F: Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>> + 'static,
+    {
+        self.route(Method::GET, template, handler)
+    }
+
+    /// Add a route for `POST` requests.
+    pub fn post<F>(self, template: &str, handler: F) -> Router
+    where
+        F: Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>> + 'static,
+    {
+        self.route(Method::POST, template, handler)
+    }
+
+    /// Add a route for `PUT` requests.
+    pub fn put<F>(self, template: &str, handler: F) -> Router
+    where
+        F: Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>> + 'static,
+    {
+        self.route(Method::PUT, template, handler)
+    }
+
+    /// Add a route for `PATCH` requests.
+    pub fn patch<F>(self, template: &str, handler: F) -> Router
+    where
+        F: Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>> + 'static,
+    {
+        self.route(Method::PATCH, template, handler)
+    }
+
+    /// Add a route for `DELETE` requests.
+    pub fn delete<F>(self, template: &str, handler: F) -> Router
+    where
+        F: Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>> + 'static,
+    {
+        self.route(Method::DELETE, template, handler)
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, req: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+        let path = req.uri().path();
+        let mut allowed: Vec<&Method> = vec![];
+        let mut get = None;
+        for route in &self.routes {
+            if let Some(params) = match_template(&route.template, path) {
+                if route.method == req.method() {
+                    return (route.handler)(req, &params);
+                }
+                if route.method == Method::GET && get.is_none() {
+                    get = Some((route, params));
+                }
+                if !allowed.contains(&&route.method) {
+                    allowed.push(&route.method);
+                }
+            }
+        }
+        if let Some((route, params)) = get {
+            if req.method() == Method::HEAD {
+                let mut resp = (route.handler)(req, &params);
+                resp.body_mut().clear();
+                return resp;
+            }
+            if !allowed.contains(&&Method::HEAD) {
+                allowed.push(&Method::HEAD);
+            }
+        }
+        if allowed.is_empty() {
+            return status_response(StatusCode::NOT_FOUND);
+        }
+        let allow = allowed
+            .iter()
+            .map(|m| m.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        let mut resp = status_response(StatusCode::METHOD_NOT_ALLOWED);
+        resp.headers_mut().insert(
+            header::ALLOW,
+            HeaderValue::from_str(&allow).expect("methods are valid header values"),
+        );
+        resp
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Vec<u8>> {
+    let mut resp = Response::new(vec![]);
+    *resp.status_mut() = status;
+    resp
+}
+
+fn parse_template(template: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    for s in template.trim_start_matches('/').split('/') {
+        if let Some(&Segment::Wildcard(_)) = segments.last() {
+            panic!(
+                "a wildcard must be the last segment of a route template: {}",
+                template
+            );
+        }
+        segments.push(if let Some(name) = s.strip_prefix(':') {
+            Segment::Param(name.to_owned())
+        } else if let Some(name) = s.strip_prefix('*') {
+            Segment::Wildcard(if name.is_empty() { "*" } else { name }.to_owned())
+        } else {
+            Segment::Literal(s.to_owned())
+        });
+    }
+    segments
+}
+
+/// Match a path against a template, returning the captured parameters.
+fn match_template(template: &[Segment], path: &str) -> Option<Params> {
+    let mut params = vec![];
+    let mut rest = Some(path.trim_start_matches('/'));
+    for segment in template {
+        if let Segment::Wildcard(ref name) = *segment {
+            params.push((name.clone(), decode(rest.unwrap_or(""))));
+            return Some(Params(params));
+        }
+        let current = rest?;
+        let (head, tail) = match current.find('/') {
+            Some(i) => (&current[..i], Some(&current[i + 1..])),
+            None => (current, None),
+        };
+        match *segment {
+            Segment::Literal(ref literal) if literal == head => {}
+            Segment::Param(ref name) if !head.is_empty() => {
+                params.push((name.clone(), decode(head)));
+            }
+            _ => return None,
+        }
+        rest = tail;
+    }
+    if rest.is_none() {
+        Some(Params(params))
+    } else {
+        None
+    }
+}
+
+/// Percent-decode a captured segment, replacing any invalid UTF-8 with
+/// `U+FFFD`.
+fn decode(segment: &str) -> String {
+    String::from_utf8_lossy(&percent_decode(segment.as_bytes(), false)).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Responds with the route that matched and the parameters it captured.
+    fn echo(name: &'static str) -> impl Fn(&Request<Vec<u8>>, &Params) -> Response<Vec<u8>> {
+        move |_, params| {
+            let mut body = name.to_owned();
+            for (k, v) in params.iter() {
+                body.push_str(&format!(" {}={}", k, v));
+            }
+            Response::new(body.into_bytes())
+        }
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", echo("index"))
+            .get("/users/:id", echo("user"))
+            .put("/users/:id", echo("put user"))
+            .delete("/users/:id", echo("delete user"))
+            .get("/users/:id/posts/:post", echo("post"))
+            .get("/users/me", echo("unreachable"))
+            .get("/static/*path", echo("static"))
+            .post("/upload/*", echo("upload"))
+    }
+
+    fn send(method: Method, uri: &str) -> Response<Vec<u8>> {
+        let mut req = Request::new(vec![]);
+        *req.method_mut() = method;
+        *req.uri_mut() = uri.parse().unwrap();
+        router().handle(&req)
+    }
+
+    fn body(resp: &Response<Vec<u8>>) -> &str {
+        std::str::from_utf8(resp.body()).unwrap()
+    }
+
+    #[test]
+    fn matches_routes() {
+        let cases = [
+            ("/", "index"),
+            ("/users/42", "user id=42"),
+            ("/users/42?full=1", "user id=42"),
+            ("/users/42/posts/7", "post id=42 post=7"),
+            // routes are tried in order, so the earlier parameter wins
+            ("/users/me", "user id=me"),
+            ("/users/a%20b", "user id=a b"),
+            ("/users/a%2Fb", "user id=a/b"),
+            ("/users/a+b%zz", "user id=a+b%zz"),
+            ("/static", "static path="),
+            ("/static/", "static path="),
+            ("/static/css/site.css", "static path=css/site.css"),
+            ("/static/a%20b/c", "static path=a b/c"),
+        ];
+        for &(path, expected) in &cases {
+            let resp = send(Method::GET, path);
+            assert_eq!(resp.status(), StatusCode::OK, "{}", path);
+            assert_eq!(body(&resp), expected, "{}", path);
+        }
+        assert_eq!(body(&send(Method::POST, "/upload/a/b")), "upload *=a/b");
+    }
+
+    #[test]
+    fn unmatched_paths_are_not_found() {
+        for path in &[
+            "/users",
+            "/users/",
+            "/users/42/",
+            "/users/42/posts",
+            "/other",
+        ] {
+            let resp = send(Method::GET, path);
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+            assert!(!resp.headers().contains_key(header::ALLOW));
+        }
+    }
+
+    #[test]
+    fn other_methods_are_not_allowed() {
+        let cases = [
+            (Method::POST, "/users/42", "GET, PUT, DELETE, HEAD"),
+            (Method::GET, "/upload/a", "POST"),
+            (Method::HEAD, "/upload/a", "POST"),
+            (Method::DELETE, "/static/x", "GET, HEAD"),
+        ];
+        for (method, path, allow) in cases.iter().cloned() {
+            let resp = send(method, path);
+            assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", path);
+            assert_eq!(resp.headers()[header::ALLOW], allow, "{}", path);
+        }
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let resp = send(Method::HEAD, "/users/42");
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.body().is_empty());
+
+        let router = router().route(Method::HEAD, "/users/:id", echo("head user"));
+        let mut req = Request::new(vec![]);
+        *req.method_mut() = Method::HEAD;
+        *req.uri_mut() = "/users/42".parse().unwrap();
+        assert_eq!(body(&router.handle(&req)), "head user id=42");
+    }
+
+    #[test]
+    #[should_panic(expected = "a wildcard must be the last segment")]
+    fn wildcard_must_be_last() {
+        Router::new().get("/a/*rest/b", echo("bad"));
+    }
+}
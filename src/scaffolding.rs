@@ -1,9 +1,19 @@
 //! Scaffolding for a guest application.
 
-use http::{Request, Response};
+use http::uri::Authority;
+use http::{Request, Response, StatusCode, Uri, Version};
+use std::future::Future;
+use std::io::{self, Read};
 
+use crate::client_info::ClientInfo;
+use crate::error::ErrorResponse;
+use crate::executor::block_on;
+use crate::hostcalls;
 pub use crate::hostcalls::types::{RequestHandle, ResponseHandle};
 use crate::kvstore::KVStore;
+use crate::panic::{catch_panic, panic_response};
+use crate::routing::Handler;
+use crate::trailers::Trailers;
 
 /// Macro to set up the scaffolding
 ///
@@ -28,9 +38,39 @@ use crate::kvstore::KVStore;
 ///
 /// guest_app!(user_entrypoint);
 /// ```
+///
+/// The entrypoint can be any `Handler`, so a `Router` can be passed
+/// directly, as in `guest_app!(router())`.
+///
+/// A `Stack` of middleware to run around the entrypoint can be passed
+/// as a second argument:
+///
+/// ```text
+/// guest_app!(user_entrypoint, Stack::new().with(RequireAuth).with(Cors));
+/// ```
+///
+/// The response sent if the entrypoint or a middleware hook panics can
+/// be given as a final `on_panic` argument, here and in every other
+/// scaffolding macro. It is sent through the `after` hooks of the
+/// middleware, and defaults to an empty `500 Internal Server Error`:
+///
+/// ```text
+/// guest_app!(user_entrypoint, on_panic = maintenance_page);
+/// guest_app!(user_entrypoint, Stack::new().with(Cors), on_panic = maintenance_page);
+/// ```
 #[macro_export]
 macro_rules! guest_app {
-    ($user_entrypoint:ident) => {
+    ($user_entrypoint:expr) => {
+        guest_app!($user_entrypoint, http_guest::Stack::new());
+    };
+    ($user_entrypoint:expr, on_panic = $fallback:expr) => {
+        guest_app!(
+            $user_entrypoint,
+            http_guest::Stack::new(),
+            on_panic = $fallback
+        );
+    };
+    ($user_entrypoint:expr, $middleware:expr) => {
         // // currently broken; see the string_pushes test
         // extern crate wee_alloc;
 
@@ -41,7 +81,16 @@ macro_rules! guest_app {
         pub extern "C" fn run() {
             http_guest::panic_set_once();
             http_guest::init_mm_default();
-            http_guest::raw_entrypoint($user_entrypoint);
+            http_guest::raw_entrypoint_with($user_entrypoint, $middleware);
+        }
+    };
+    ($user_entrypoint:expr, $middleware:expr, on_panic = $fallback:expr) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::set_panic_response($fallback);
+            http_guest::raw_entrypoint_with($user_entrypoint, $middleware);
         }
     };
 }
@@ -66,9 +115,22 @@ macro_rules! guest_app {
 ///
 /// guest_app_kvs!(user_entrypoint);
 /// ```
+///
+/// As with `guest_app`, a `Stack` of middleware can be passed as a
+/// second argument, and the panic fallback as `on_panic`.
 #[macro_export]
 macro_rules! guest_app_kvs {
     ($user_entrypoint:ident) => {
+        guest_app_kvs!($user_entrypoint, http_guest::Stack::new());
+    };
+    ($user_entrypoint:ident, on_panic = $fallback:expr) => {
+        guest_app_kvs!(
+            $user_entrypoint,
+            http_guest::Stack::new(),
+            on_panic = $fallback
+        );
+    };
+    ($user_entrypoint:ident, $middleware:expr) => {
         // // currently broken; see the string_pushes test
         // extern crate wee_alloc;
 
@@ -79,7 +141,159 @@ macro_rules! guest_app_kvs {
         pub extern "C" fn run() {
             http_guest::panic_set_once();
             http_guest::init_mm_default();
-            http_guest::raw_entrypoint_kvs($user_entrypoint);
+            http_guest::raw_entrypoint_kvs_with($user_entrypoint, $middleware);
+        }
+    };
+    ($user_entrypoint:ident, $middleware:expr, on_panic = $fallback:expr) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::set_panic_response($fallback);
+            http_guest::raw_entrypoint_kvs_with($user_entrypoint, $middleware);
+        }
+    };
+}
+
+/// Variation on `guest_app` for handlers that return a `Result`, so
+/// that errors can be propagated with `?`.
+///
+/// An `Err` is turned into the response sent to the client by its
+/// `ErrorResponse` implementation, which by default is a 500 with a
+/// JSON problem body. A handler that returns `failure::Error` can
+/// propagate any `Fail` with `?`, and every error it returns becomes a
+/// 500.
+///
+/// ```text
+/// #[macro_use]
+/// extern crate http_guest;
+///
+/// use http_guest::{Request, RequestExt, Response, SendError};
+///
+/// pub fn user_entrypoint(req: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, SendError> {
+///     let upstream = Request::get("https://example.com/").body(vec![]).unwrap().send()?;
+///     Ok(Response::builder().status(200).body(upstream.into_body()).unwrap())
+/// }
+///
+/// guest_app_result!(user_entrypoint);
+/// ```
+///
+/// The handler can also return anything that converts into a
+/// `Response`, such as a `Json` value with the `json` feature.
+///
+/// As with `guest_app`, the response sent if the handler panics can be
+/// given as `on_panic`:
+///
+/// ```text
+/// guest_app_result!(user_entrypoint, on_panic = maintenance_page);
+/// ```
+#[macro_export]
+macro_rules! guest_app_result {
+    ($user_entrypoint:ident) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::raw_entrypoint_result($user_entrypoint);
+        }
+    };
+    ($user_entrypoint:ident, on_panic = $fallback:expr) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::set_panic_response($fallback);
+            http_guest::raw_entrypoint_result($user_entrypoint);
+        }
+    };
+}
+
+/// Variation on `guest_app` for applications that stream request and
+/// response bodies rather than holding them in memory.
+///
+/// The body of the request is the `RequestHandle` for the incoming
+/// request, which can be read in chunks. The body of the response can
+/// be any `Read`, and is copied to the client in chunks after the
+/// status and headers have been sent. If reading the body fails or
+/// panics once the copy has begun, the response ends early instead.
+/// A proxy can pass an upstream body straight through:
+///
+/// ```text
+/// #[macro_use]
+/// extern crate http_guest;
+///
+/// use http_guest::{Request, RequestExt, RequestHandle, Response, ResponseHandle};
+///
+/// pub fn user_entrypoint(req: Request<RequestHandle>) -> Response<ResponseHandle> {
+///     let upstream = format!("https://media.example.com{}", req.uri().path());
+///     Request::get(upstream)
+///         .body(vec![])
+///         .unwrap()
+///         .send_streaming()
+///         .unwrap()
+/// }
+///
+/// guest_app_streaming!(user_entrypoint);
+/// ```
+#[macro_export]
+macro_rules! guest_app_streaming {
+    ($user_entrypoint:ident) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::raw_entrypoint_streaming($user_entrypoint);
+        }
+    };
+    ($user_entrypoint:ident, on_panic = $fallback:expr) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::set_panic_response($fallback);
+            http_guest::raw_entrypoint_streaming($user_entrypoint);
+        }
+    };
+}
+
+/// Variation on `guest_app` for `async` handlers, which can await
+/// outbound requests sent with `send_async()`.
+///
+/// The handler takes the request by value, and its future is run to
+/// completion by `executor::block_on`.
+///
+/// ```text
+/// #[macro_use]
+/// extern crate http_guest;
+///
+/// use http_guest::{executor, Request, RequestExt, Response};
+///
+/// pub async fn user_entrypoint(req: Request<Vec<u8>>) -> Response<Vec<u8>> {
+///     let a = Request::get("https://a.example/").body(vec![]).unwrap().send_async().unwrap();
+///     let b = Request::get("https://b.example/").body(vec![]).unwrap().send_async().unwrap();
+///     let bodies = executor::join_futures(vec![a, b]).await;
+///     Response::builder().status(200).body(vec![]).unwrap()
+/// }
+///
+/// guest_app_async!(user_entrypoint);
+/// ```
+#[macro_export]
+macro_rules! guest_app_async {
+    ($user_entrypoint:ident) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::raw_entrypoint_async($user_entrypoint);
+        }
+    };
+    ($user_entrypoint:ident, on_panic = $fallback:expr) => {
+        #[no_mangle]
+        pub extern "C" fn run() {
+            http_guest::panic_set_once();
+            http_guest::init_mm_default();
+            http_guest::set_panic_response($fallback);
+            http_guest::raw_entrypoint_async($user_entrypoint);
         }
     };
 }
@@ -91,49 +305,588 @@ macro_rules! guest_app_kvs {
 /// should not be called directly by user code. We hide the doc so
 /// that users aren't encouraged to mess with it, but it needs to be
 /// exported so the macro can work.
+///
+/// If the user entrypoint panics, the response set with
+/// `set_panic_response` is sent in place of its response.
+#[doc(hidden)]
+pub fn raw_entrypoint<H: Handler>(user_entrypoint: H) {
+    raw_entrypoint_with(user_entrypoint, Stack::new());
+}
+
+/// Like `raw_entrypoint`, but runs the user entrypoint inside the
+/// `middleware` stack.
+///
+/// If the user entrypoint or a middleware hook panics, the response
+/// set with `set_panic_response` is sent in place of its response.
 #[doc(hidden)]
-pub fn raw_entrypoint<F>(user_entrypoint: F)
+pub fn raw_entrypoint_with<H: Handler>(user_entrypoint: H, middleware: Stack) {
+    let resp = match build_req() {
+        Ok(req) => middleware.run(req, |req| user_entrypoint.handle(req)),
+        Err(_) => bad_request(),
+    };
+    build_resp(resp);
+}
+
+pub fn raw_entrypoint_kvs<F>(user_entrypoint: F)
 where
-    F: Fn(&Request<Vec<u8>>) -> Response<Vec<u8>>,
+    F: Fn(&mut KVStore, &Request<Vec<u8>>) -> Response<Vec<u8>>,
 {
-    build_resp(user_entrypoint(&build_req()));
+    raw_entrypoint_kvs_with(user_entrypoint, Stack::new());
 }
 
-pub fn raw_entrypoint_kvs<F>(user_entrypoint: F)
+pub fn raw_entrypoint_kvs_with<F>(user_entrypoint: F, middleware: Stack)
 where
     F: Fn(&mut KVStore, &Request<Vec<u8>>) -> Response<Vec<u8>>,
 {
-    build_resp(user_entrypoint(&mut KVStore::global(), &build_req()));
+    let resp = match build_req() {
+        Ok(req) => middleware.run(req, |req| user_entrypoint(&mut KVStore::global(), req)),
+        Err(_) => bad_request(),
+    };
+    build_resp(resp);
+}
+
+pub fn raw_entrypoint_result<F, R, E>(user_entrypoint: F)
+where
+    F: Fn(&Request<Vec<u8>>) -> Result<R, E>,
+    R: Into<Response<Vec<u8>>>,
+    E: ErrorResponse,
+{
+    let req = match build_req() {
+        Ok(req) => req,
+        Err(_) => return build_resp(bad_request()),
+    };
+    let resp = match catch_panic(|| user_entrypoint(&req).map(Into::into)) {
+        Some(Ok(resp)) => resp,
+        Some(Err(e)) => e.error_response(),
+        None => panic_response(),
+    };
+    build_resp(resp);
+}
+
+pub fn raw_entrypoint_async<F, Fut>(user_entrypoint: F)
+where
+    F: Fn(Request<Vec<u8>>) -> Fut,
+    Fut: Future<Output = Response<Vec<u8>>>,
+{
+    let req = match build_req() {
+        Ok(req) => req,
+        Err(_) => return build_resp(bad_request()),
+    };
+    let resp = catch_panic(|| block_on(user_entrypoint(req)));
+    build_resp(resp.unwrap_or_else(panic_response));
 }
 
+pub fn raw_entrypoint_streaming<F, B>(user_entrypoint: F)
+where
+    F: Fn(Request<RequestHandle>) -> Response<B>,
+    B: Read,
+{
+    let req = match build_req_head().body(RequestHandle::INCOMING) {
+        Ok(req) => req,
+        Err(_) => return build_resp(bad_request()),
+    };
+    match catch_panic(|| user_entrypoint(req)) {
+        Some(resp) => build_resp_streaming(resp),
+        None => build_resp(panic_response()),
+    }
+}
+
+/// Code that runs around the user entrypoint, such as logging, CORS,
+/// or authentication.
+///
+/// Both hooks do nothing by default, so an implementation only needs
+/// to provide the ones it uses.
+pub trait Middleware {
+    /// Inspect or modify the request before the entrypoint sees it.
+    ///
+    /// Returning a response short-circuits the stack: neither the
+    /// entrypoint nor any later middleware runs, and the response goes
+    /// back out through the `after` hooks of the middleware that ran
+    /// before this one.
+    fn before(&self, _req: &mut Request<Vec<u8>>) -> Option<Response<Vec<u8>>> {
+        None
+    }
+
+    /// Inspect or modify the response on its way back to the client.
+    fn after(&self, _req: &Request<Vec<u8>>, _resp: &mut Response<Vec<u8>>) {}
+}
+
+/// An ordered stack of middleware, for `guest_app!` to run around the
+/// user entrypoint.
+///
+/// The `before` hooks run in the order the middleware was added, and
+/// the `after` hooks in the reverse order, so the first middleware
+/// added sees the request first and the response last. The `after`
+/// hooks also see the fallback response sent when the entrypoint
+/// panics, which is given to the macro as `on_panic`.
+#[derive(Default)]
+pub struct Stack {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Stack {
+    pub fn new() -> Stack {
+        Stack::default()
+    }
+
+    /// Add middleware inside all of the middleware added so far.
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Stack {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Run the request through the stack and the entrypoint, returning
+    /// the response to send.
+    fn run<F>(&self, req: Request<Vec<u8>>, user_entrypoint: F) -> Response<Vec<u8>>
+    where
+        F: FnOnce(&Request<Vec<u8>>) -> Response<Vec<u8>>,
+    {
+        let mut req = req;
+        let result = catch_panic(|| {
+            let mut ran = 0;
+            let mut short_circuit = None;
+            for middleware in &self.middleware {
+                short_circuit = middleware.before(&mut req);
+                if short_circuit.is_some() {
+                    break;
+                }
+                ran += 1;
+            }
+            let mut resp = short_circuit.unwrap_or_else(|| {
+                catch_panic(|| user_entrypoint(&req)).unwrap_or_else(panic_response)
+            });
+            for middleware in self.middleware[..ran].iter().rev() {
+                middleware.after(&req, &mut resp);
+            }
+            resp
+        });
+        result.unwrap_or_else(panic_response)
+    }
+}
+
+/// A reason phrase to send in place of the standard one for the
+/// response's status code.
+///
+/// Insert this into the extensions of the `Response` returned by the
+/// user entrypoint:
+///
+/// ```text
+/// let mut resp = Response::builder().status(200).body(vec![]).unwrap();
+/// resp.extensions_mut().insert(ReasonPhrase("Okey Dokey".to_string()));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReasonPhrase(pub String);
+
 /// Build up the `Request` from the hostcall interface
-fn build_req() -> Request<Vec<u8>> {
+fn build_req() -> Result<Request<Vec<u8>>, http::Error> {
+    let mut req = build_req_head().body(RequestHandle::INCOMING.get_body())?;
+    if let Some(trailers) = Trailers::from_request(&RequestHandle::INCOMING) {
+        req.extensions_mut().insert(trailers);
+    }
+    Ok(req)
+}
+
+/// The response sent in place of running the entrypoint when the
+/// incoming request cannot be represented as a `Request`, such as one
+/// whose method or headers the `http` crate rejects
+fn bad_request() -> Response<Vec<u8>> {
+    let mut resp = Response::new(vec![]);
+    *resp.status_mut() = StatusCode::BAD_REQUEST;
+    resp
+}
+
+/// Start building the `Request` with the method, URI, version, headers
+/// and `ClientInfo` of the incoming request
+fn build_req_head() -> http::request::Builder {
     let inc = RequestHandle::INCOMING;
     let mut builder = Request::builder();
     for name in inc.get_headers() {
-        for value in inc.get_header(&name) {
-            builder.header(name.as_str(), value);
+        for value in inc.get_header_bytes(&name) {
+            builder.header(name.as_str(), &value[..]);
         }
     }
     builder
         .method(inc.get_method().as_str())
-        .uri(inc.get_path())
-        .body(inc.get_body())
-        .unwrap()
+        .uri(build_uri(&inc))
+        .version(inc.get_version())
+        .extension(ClientInfo::incoming());
+    builder
+}
+
+/// Build the full URI of the incoming request, which is only absolute
+/// if the host knows its authority.
+///
+/// The authority comes from the client's `Host` header, so one that is
+/// not valid leaves the URI relative, and a query that is not valid is
+/// dropped.
+fn build_uri(inc: &RequestHandle) -> Uri {
+    let path = inc.get_path();
+    let query = inc.get_query();
+    let path_and_query = if query.is_empty() {
+        path.clone()
+    } else {
+        format!("{}?{}", path, query)
+    };
+    let authority = inc.get_authority();
+    let absolute = if authority.is_empty() {
+        None
+    } else {
+        authority.parse::<Authority>().ok().and_then(|_| {
+            format!("{}://{}{}", inc.get_scheme(), authority, path_and_query)
+                .parse()
+                .ok()
+        })
+    };
+    absolute
+        .or_else(|| path_and_query.parse().ok())
+        .or_else(|| path.parse().ok())
+        .unwrap_or_else(|| Uri::from_static("/"))
 }
 
 /// Output the `Response` via hostcalls
 fn build_resp(resp: Response<Vec<u8>>) {
     let mut out = ResponseHandle::OUTGOING;
+    set_resp_head(&mut out, &resp);
+    out.set_body(resp.body());
+    set_resp_trailers(&mut out, &resp);
+}
+
+/// Output the head of the `Response` via hostcalls, then copy its body
+/// to the client in chunks, stopping early if the body fails or panics
+fn build_resp_streaming<B: Read>(resp: Response<B>) {
+    let mut out = ResponseHandle::OUTGOING;
+    set_resp_head(&mut out, &resp);
+    let (parts, mut body) = resp.into_parts();
+    // the head has already been sent, so all that can be done if the
+    // body fails is to end it early, without trailers that would vouch
+    // for it
+    match catch_panic(|| io::copy(&mut body, &mut out)) {
+        Some(Ok(_)) => {}
+        Some(Err(e)) => {
+            hostcalls::debug(&format!("response body could not be streamed: {}", e));
+            return;
+        }
+        None => {
+            hostcalls::debug("response body panicked while being streamed");
+            return;
+        }
+    }
+    if let Some(trailers) = parts.extensions.get::<Trailers>() {
+        trailers.set_on_response(&mut out);
+    }
+}
+
+/// Output the trailers of the `Response` via hostcalls
+fn set_resp_trailers<B>(out: &mut ResponseHandle, resp: &Response<B>) {
+    if let Some(trailers) = resp.extensions().get::<Trailers>() {
+        trailers.set_on_response(out);
+    }
+}
+
+/// Output the status, version, reason phrase and headers of the
+/// `Response` via hostcalls
+///
+/// HTTP/1.1 is the default version of a `Response`, so it is not sent,
+/// and the host answers in the version it negotiated with the client
+/// rather than downgrading a client that spoke a later one.
+fn set_resp_head<B>(out: &mut ResponseHandle, resp: &Response<B>) {
     let headers = resp.headers();
     for name in headers.keys() {
-        let values: Vec<&str> = headers
-            .get_all(name)
-            .iter()
-            .map(|v| v.to_str().unwrap())
-            .collect();
-        out.set_header(name.as_str(), &values);
+        let values: Vec<&[u8]> = headers.get_all(name).iter().map(|v| v.as_bytes()).collect();
+        out.set_header_bytes(name.as_str(), &values);
     }
-    out.set_body(resp.body());
     out.set_response_code(resp.status().as_u16());
+    if resp.version() != Version::HTTP_11 {
+        out.set_version(resp.version());
+    }
+    if let Some(ReasonPhrase(reason)) = resp.extensions().get::<ReasonPhrase>() {
+        out.set_reason(reason);
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::client::SendError;
+    use crate::mock;
+    use crate::panic::set_panic_response;
+    use failure::Fail;
+
+    struct Tag;
+
+    impl Middleware for Tag {
+        fn after(&self, _req: &Request<Vec<u8>>, resp: &mut Response<Vec<u8>>) {
+            resp.headers_mut().insert("x-tag", "1".parse().unwrap());
+        }
+    }
+
+    struct PanicBefore;
+
+    impl Middleware for PanicBefore {
+        fn before(&self, _req: &mut Request<Vec<u8>>) -> Option<Response<Vec<u8>>> {
+            panic!("before")
+        }
+    }
+
+    fn teapot() -> Response<Vec<u8>> {
+        let mut resp = Response::new(b"short and stout".to_vec());
+        *resp.status_mut() = StatusCode::IM_A_TEAPOT;
+        resp
+    }
+
+    fn panics(_: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+        panic!("boom")
+    }
+
+    fn run(stack: Stack) -> Response<Vec<u8>> {
+        mock::run_with(Request::new(vec![]), panics, stack)
+    }
+
+    #[test]
+    fn panic_gets_the_default_fallback() {
+        let resp = run(Stack::new().with(Tag));
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resp.headers()["x-tag"], "1");
+    }
+
+    #[test]
+    fn fallback_is_sent_through_the_middleware() {
+        set_panic_response(teapot);
+        let resp = run(Stack::new().with(Tag));
+        assert_eq!(resp.status(), StatusCode::IM_A_TEAPOT);
+        assert_eq!(resp.body(), b"short and stout");
+        assert_eq!(resp.headers()["x-tag"], "1");
+    }
+
+    #[test]
+    fn fallback_is_used_when_middleware_panics() {
+        set_panic_response(teapot);
+        let resp = run(Stack::new().with(PanicBefore));
+        assert_eq!(resp.status(), StatusCode::IM_A_TEAPOT);
+        assert!(!resp.headers().contains_key("x-tag"));
+    }
+
+    /// Responds with the version and URI of the request as the guest sees it.
+    fn echo_uri(req: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+        Response::new(format!("{:?} {}", req.version(), req.uri()).into_bytes())
+    }
+
+    fn seen(req: Request<Vec<u8>>) -> String {
+        let resp = mock::run(req, echo_uri);
+        assert_eq!(resp.status(), StatusCode::OK);
+        String::from_utf8(resp.into_body()).unwrap()
+    }
+
+    #[test]
+    fn uri_is_absolute_when_the_authority_is_known() {
+        let req = Request::get("/users?page=2")
+            .header("host", "example.com:8080")
+            .body(vec![])
+            .unwrap();
+        assert_eq!(seen(req), "HTTP/1.1 http://example.com:8080/users?page=2");
+
+        let req = Request::get("https://example.com/a/b")
+            .body(vec![])
+            .unwrap();
+        assert_eq!(seen(req), "HTTP/1.1 https://example.com/a/b");
+    }
+
+    #[test]
+    fn uri_is_relative_without_an_authority() {
+        let req = Request::get("/users?page=2").body(vec![]).unwrap();
+        assert_eq!(seen(req), "HTTP/1.1 /users?page=2");
+    }
+
+    #[test]
+    fn invalid_host_header_leaves_the_uri_relative() {
+        for host in &["bad host", "a/b", "x@", ""] {
+            let req = Request::get("/users?page=2")
+                .header("host", *host)
+                .body(vec![])
+                .unwrap();
+            assert_eq!(seen(req), "HTTP/1.1 /users?page=2", "{:?}", host);
+            assert!(mock::panic_messages().is_empty());
+        }
+    }
+
+    #[test]
+    fn request_version_is_passed_through() {
+        let req = Request::get("/")
+            .version(Version::HTTP_10)
+            .body(vec![])
+            .unwrap();
+        assert_eq!(seen(req), "HTTP/1.0 /");
+    }
+
+    fn http2_request() -> Request<Vec<u8>> {
+        Request::get("/")
+            .version(Version::HTTP_2)
+            .body(vec![])
+            .unwrap()
+    }
+
+    #[test]
+    fn default_response_leaves_the_version_to_the_host() {
+        let resp = mock::run(http2_request(), |_: &Request<Vec<u8>>| {
+            Response::new(vec![])
+        });
+        assert_eq!(resp.version(), Version::HTTP_2);
+    }
+
+    #[test]
+    fn response_version_is_forwarded() {
+        let resp = mock::run(http2_request(), |_: &Request<Vec<u8>>| {
+            let mut resp = Response::new(vec![]);
+            *resp.version_mut() = Version::HTTP_10;
+            resp
+        });
+        assert_eq!(resp.version(), Version::HTTP_10);
+    }
+
+    /// Yields `data`, then fails.
+    struct Failing(io::Cursor<Vec<u8>>);
+
+    impl Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "upstream went away",
+                )),
+                n => Ok(n),
+            }
+        }
+    }
+
+    fn with_trailer<B>(mut resp: Response<B>) -> Response<B> {
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("x-checksum", "abc".parse().unwrap());
+        resp.extensions_mut().insert(Trailers(trailers));
+        resp
+    }
+
+    #[test]
+    fn streaming_response_body_is_copied_to_the_client() {
+        let body = (0..20_000).map(|i| i as u8).collect::<Vec<u8>>();
+        let expected = body.clone();
+        let resp = mock::run_streaming(Request::new(vec![]), move |_: Request<RequestHandle>| {
+            let mut resp = Response::new(io::Cursor::new(body.clone()));
+            *resp.status_mut() = StatusCode::PARTIAL_CONTENT;
+            resp.headers_mut()
+                .insert("content-type", "application/octet-stream".parse().unwrap());
+            with_trailer(resp)
+        });
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()["content-type"], "application/octet-stream");
+        assert_eq!(resp.body(), &expected);
+        let Trailers(trailers) = resp.extensions().get::<Trailers>().unwrap();
+        assert_eq!(trailers["x-checksum"], "abc");
+    }
+
+    #[test]
+    fn failing_streaming_body_ends_the_response_without_panicking() {
+        let resp = mock::run_streaming(Request::new(vec![]), |_: Request<RequestHandle>| {
+            with_trailer(Response::new(Failing(io::Cursor::new(b"partial".to_vec()))))
+        });
+        assert!(
+            mock::panic_messages().is_empty(),
+            "{:?}",
+            mock::panic_messages()
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.body(), b"partial");
+        assert!(resp.extensions().get::<Trailers>().is_none());
+        assert_eq!(
+            mock::debug_messages(),
+            ["response body could not be streamed: upstream went away"]
+        );
+    }
+
+    /// Yields `data`, then panics.
+    struct Panicking(io::Cursor<Vec<u8>>);
+
+    impl Read for Panicking {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => panic!("body reader panicked"),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn panicking_streaming_body_ends_the_response() {
+        let resp = mock::run_streaming(Request::new(vec![]), |_: Request<RequestHandle>| {
+            with_trailer(Response::new(Panicking(io::Cursor::new(
+                b"partial".to_vec(),
+            ))))
+        });
+        assert_eq!(mock::panic_messages().len(), 1);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.body(), b"partial");
+        assert!(resp.extensions().get::<Trailers>().is_none());
+        assert_eq!(
+            mock::debug_messages(),
+            ["response body panicked while being streamed"]
+        );
+    }
+
+    #[test]
+    fn header_bytes_round_trip() {
+        let req = Request::get("/")
+            .header("x-bin", &b"caf\xe9"[..])
+            .body(vec![])
+            .unwrap();
+        let resp = mock::run(req, |req: &Request<Vec<u8>>| {
+            let mut resp = Response::new(vec![]);
+            for value in req.headers().get_all("x-bin") {
+                resp.headers_mut().append("x-echo", value.clone());
+            }
+            resp
+        });
+        let echoed = resp.headers().get_all("x-echo").iter().collect::<Vec<_>>();
+        assert_eq!(echoed.len(), 1);
+        assert_eq!(echoed[0].as_bytes(), b"caf\xe9");
+    }
+
+    #[derive(Debug, Fail)]
+    #[fail(display = "no such user: {}", _0)]
+    struct NoSuchUser(String);
+
+    #[derive(Debug, Fail)]
+    #[fail(display = "user is banned")]
+    struct Banned;
+
+    impl ErrorResponse for Banned {
+        fn error_response(&self) -> Response<Vec<u8>> {
+            crate::error::problem_response(StatusCode::FORBIDDEN, self)
+        }
+    }
+
+    #[test]
+    fn any_fail_through_failure_error_is_a_500() {
+        let resp = mock::run_result(Request::new(vec![]), |_: &Request<Vec<u8>>| {
+            Err::<Response<Vec<u8>>, failure::Error>(NoSuchUser("bob".to_string()).into())
+        });
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(String::from_utf8(resp.into_body())
+            .unwrap()
+            .contains(r#""detail":"no such user: bob""#));
+    }
+
+    #[test]
+    fn returned_error_uses_its_error_response() {
+        let resp = mock::run_result(Request::new(vec![]), |_: &Request<Vec<u8>>| {
+            Err::<Response<Vec<u8>>, _>(Banned)
+        });
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn crate_errors_use_their_error_response() {
+        let resp = mock::run_result(Request::new(vec![]), |_: &Request<Vec<u8>>| {
+            Err::<Response<Vec<u8>>, _>(SendError::TooManyRedirects(3))
+        });
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    }
 }
@@ -0,0 +1,99 @@
+//! Request and upstream fixtures shared by the crate's unit tests.
+
+use crate::error::ErrorResponse;
+use http::{header, Request};
+use std::time::Duration;
+
+#[cfg(feature = "mock")]
+use crate::client::{PendingRequest, RequestExt};
+#[cfg(feature = "mock")]
+use crate::hostcalls::types::ErrorCode;
+#[cfg(feature = "mock")]
+use crate::mock::{self, FakeUpstream, Reply};
+#[cfg(feature = "mock")]
+use http::Method;
+
+/// A `GET` request for `uri` with an empty body.
+pub(crate) fn get(uri: &str) -> Request<Vec<u8>> {
+    Request::get(uri).body(vec![]).unwrap()
+}
+
+/// A `POST` request for `uri` carrying `body`, with a `Content-Type` header if one is given.
+pub(crate) fn post<B: AsRef<[u8]>>(
+    uri: &str,
+    content_type: Option<&str>,
+    body: B,
+) -> Request<Vec<u8>> {
+    let mut req = Request::post(uri);
+    if let Some(content_type) = content_type {
+        req.header(header::CONTENT_TYPE, content_type);
+    }
+    req.body(body.as_ref().to_vec()).unwrap()
+}
+
+/// The status of the response that `e` is sent as.
+pub(crate) fn error_status<E: ErrorResponse>(e: E) -> u16 {
+    e.error_response().status().as_u16()
+}
+
+pub(crate) fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// Send a `GET` request for `url` without waiting for the response.
+#[cfg(feature = "mock")]
+pub(crate) fn send(url: &str) -> PendingRequest {
+    get(url).send_async().unwrap()
+}
+
+/// Upstreams that answer after a fixed latency: `slow` (200 after 20ms), `fast` (201 after
+/// 10ms), `slower` (202 after 30ms), `down` (a failure after 5ms) and `timeout` (a timeout
+/// error after 8ms).
+#[cfg(feature = "mock")]
+pub(crate) fn timed_upstream() {
+    mock::set_fake_upstream(
+        FakeUpstream::new()
+            .route_any("http://slow/*", Reply::status(200).latency(ms(20)))
+            .route_any("http://fast/*", Reply::status(201).latency(ms(10)))
+            .route_any("http://slower/*", Reply::status(202).latency(ms(30)))
+            .route_any("http://down/*", Reply::failure().latency(ms(5)))
+            .route_any(
+                "http://timeout/*",
+                Reply::error(ErrorCode::Timeout, "timed out").latency(ms(8)),
+            ),
+    );
+}
+
+/// `http://up/a` to `http://up/d`, each answering with its own name after a different
+/// latency, so they complete in the order d, b, c, a; and `http://down/`, which fails.
+#[cfg(feature = "mock")]
+pub(crate) fn tagged_upstream() {
+    let upstream = [("a", 35), ("b", 10), ("c", 20), ("d", 3)].iter().fold(
+        FakeUpstream::new(),
+        |upstream, &(tag, latency)| {
+            upstream.route_any(
+                &format!("http://up/{}", tag),
+                Reply::status(200).body(tag).latency(ms(latency)),
+            )
+        },
+    );
+    mock::set_fake_upstream(upstream.route_any("http://down/", Reply::failure()));
+}
+
+/// `http://a/1` redirects to `/2`, which redirects to `/3`, which answers "done". A `POST` to
+/// `http://a/form` is answered with a 303 to `/3`.
+#[cfg(feature = "mock")]
+pub(crate) fn redirect_upstream() {
+    let hop = |to: &str| Reply::status(302).header("location", to);
+    mock::set_fake_upstream(
+        FakeUpstream::new()
+            .route_any("http://a/1", hop("/2"))
+            .route_any("http://a/2", hop("/3"))
+            .route_any("http://a/3", Reply::status(200).body("done"))
+            .route(
+                Method::POST,
+                "http://a/form",
+                Reply::status(303).header("location", "/3"),
+            ),
+    );
+}
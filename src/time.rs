@@ -1,5 +1,7 @@
-use crate::hostcalls::raw::hostcall_time_now;
+use crate::hostcalls::duration_ms;
+use crate::hostcalls::raw::{hostcall_time_now, hostcall_time_sleep};
 use coarsetime::Duration;
+use std::time;
 
 pub struct Time {}
 
@@ -9,4 +11,9 @@ impl Time {
         let secs = unsafe { hostcall_time_now(&mut subsec_nanos) };
         Duration::new(secs, subsec_nanos)
     }
+
+    /// Block for at least `duration`, rounded up to whole milliseconds.
+    pub fn sleep(duration: time::Duration) {
+        unsafe { hostcall_time_sleep(duration_ms(duration)) }
+    }
 }
@@ -0,0 +1,81 @@
+//! HTTP trailers, carried in the extensions of requests and responses.
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::hostcalls::{HostcallStatus, RequestHandle, ResponseHandle};
+
+/// The trailers sent after the body of a request or response.
+///
+/// The scaffolding inserts this into the extensions of the incoming request, and the client into
+/// those of responses, whenever trailers were received. Inserting it into the extensions of an
+/// outbound request, or of the response returned by the user entrypoint, sends the trailers:
+///
+/// ```text
+/// let mut trailers = HeaderMap::new();
+/// trailers.insert("grpc-status", HeaderValue::from_static("0"));
+/// resp.extensions_mut().insert(Trailers(trailers));
+/// ```
+///
+/// Requests and responses whose bodies are streamed do not carry trailers in their extensions,
+/// because they are not known until the body has been read; use `get_trailers()` on the handle
+/// once it has been.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trailers(pub HeaderMap);
+
+impl Trailers {
+    /// Get the trailers of a request from the host, if it has any.
+    pub(crate) fn from_request(req: &RequestHandle) -> Option<Trailers> {
+        collect(req.get_trailers(), |name| req.get_trailer_bytes(name))
+    }
+
+    /// Get the trailers of a response from the host, if it has any.
+    pub(crate) fn from_response(resp: &ResponseHandle) -> Option<Trailers> {
+        collect(resp.get_trailers(), |name| resp.get_trailer_bytes(name))
+    }
+
+    /// Set these trailers on an outbound request.
+    pub(crate) fn set_on_request(&self, req: &mut RequestHandle) -> HostcallStatus {
+        self.set_each(|name, values| req.set_trailer_bytes(name, values))
+    }
+
+    /// Set these trailers on the outgoing response.
+    pub(crate) fn set_on_response(&self, resp: &mut ResponseHandle) -> HostcallStatus {
+        self.set_each(|name, values| resp.set_trailer_bytes(name, values))
+    }
+
+    fn set_each<F>(&self, mut set: F) -> HostcallStatus
+    where
+        F: FnMut(&str, &[&[u8]]) -> HostcallStatus,
+    {
+        for name in self.0.keys() {
+            let values: Vec<&[u8]> = self.0.get_all(name).iter().map(|v| v.as_bytes()).collect();
+            if set(name.as_str(), &values) == HostcallStatus::Invalid {
+                return HostcallStatus::Invalid;
+            }
+        }
+        HostcallStatus::Ok
+    }
+}
+
+fn collect<F>(names: Vec<String>, get: F) -> Option<Trailers>
+where
+    F: Fn(&str) -> Vec<Vec<u8>>,
+{
+    if names.is_empty() {
+        return None;
+    }
+    let mut trailers = HeaderMap::new();
+    for name in names {
+        // the host only reports valid names and values, so anything else is skipped
+        let name = match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        for value in get(name.as_str()) {
+            if let Ok(value) = HeaderValue::from_bytes(&value) {
+                trailers.append(&name, value);
+            }
+        }
+    }
+    Some(Trailers(trailers))
+}